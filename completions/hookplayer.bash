@@ -0,0 +1,67 @@
+# Bash completion for hookplayer. Load it from ~/.bashrc:
+#
+#   source /path/to/hookplayer.bash
+#
+# zsh can use it too, after `autoload -U bashcompinit && bashcompinit`.
+#
+# Sound arguments to `event add` and `event rm` complete relative to the
+# sounds directory, whatever the current directory is.
+
+_hookplayer_commands="update setup list search info download check config event resolve schedule volume
+mute unmute snooze status restore-backup dir set-dir use install outdated upgrade remove packs"
+
+# Configured event names, from `hookplayer event list`.
+_hookplayer_events() {
+    hookplayer event list 2>/dev/null | awk '/^  [^ ]/ { print $1 }'
+}
+
+# Files and directories under the sounds directory matching $1.
+_hookplayer_sounds() {
+    local dir entry
+    dir=$(hookplayer dir 2>/dev/null) || return
+    [ -d "$dir" ] || return
+    COMPREPLY=()
+    while IFS= read -r entry; do
+        if [ -d "$dir/$entry" ]; then
+            COMPREPLY+=("$entry/")
+        else
+            COMPREPLY+=("$entry")
+        fi
+    done < <(cd "$dir" && compgen -f -- "$1")
+    # Let a directory be completed into without a space after it.
+    compopt -o nospace 2>/dev/null
+    if [ ${#COMPREPLY[@]} -eq 1 ] && [ "${COMPREPLY[0]%/}" = "${COMPREPLY[0]}" ]; then
+        COMPREPLY[0]+=" "
+    fi
+}
+
+_hookplayer() {
+    local cur=${COMP_WORDS[COMP_CWORD]}
+    local cmd=${COMP_WORDS[1]} sub=${COMP_WORDS[2]}
+
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        COMPREPLY=($(compgen -W "$_hookplayer_commands" -- "$cur"))
+        return
+    fi
+
+    case $cmd in
+        event)
+            case $COMP_CWORD,$sub in
+                2,*) COMPREPLY=($(compgen -W "list add rm copy" -- "$cur")) ;;
+                3,add | 3,rm | 3,copy | 4,copy) COMPREPLY=($(compgen -W "$(_hookplayer_events)" -- "$cur")) ;;
+                *,add | 4,rm) _hookplayer_sounds "$cur" ;;
+            esac
+            ;;
+        resolve)
+            [ "$COMP_CWORD" -eq 2 ] && COMPREPLY=($(compgen -W "$(_hookplayer_events)" -- "$cur"))
+            ;;
+        config)
+            [ "$COMP_CWORD" -eq 2 ] && COMPREPLY=($(compgen -W "get set unset migrate" -- "$cur"))
+            ;;
+        remove | upgrade)
+            [ "$COMP_CWORD" -eq 2 ] && COMPREPLY=($(compgen -W "$(ls "$(hookplayer dir 2>/dev/null)" 2>/dev/null)" -- "$cur"))
+            ;;
+    esac
+}
+
+complete -F _hookplayer hookplayer
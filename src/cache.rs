@@ -0,0 +1,227 @@
+use crate::fsutil;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// How long a cached registry index or manifest is used without asking the
+/// server whether it changed.
+pub const TTL_SECS: u64 = 60 * 60;
+
+/// How cached responses are used, set by `--offline` and `--refresh`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Mode {
+    /// Use fresh entries as-is, revalidate stale ones.
+    #[default]
+    Normal,
+    /// Never touch the network; only cached entries are available.
+    Offline,
+    /// Revalidate every entry, however fresh.
+    Refresh,
+}
+
+impl Mode {
+    /// Removes `--offline` and `--refresh` from `args`.
+    pub fn take_flags(args: &mut Vec<String>) -> Result<Mode, String> {
+        let mut take = |flag: &str| {
+            let found = args.iter().any(|a| a == flag);
+            args.retain(|a| a != flag);
+            found
+        };
+        match (take("--offline"), take("--refresh")) {
+            (true, true) => Err("--offline and --refresh can't be used together".to_string()),
+            (true, false) => Ok(Mode::Offline),
+            (false, true) => Ok(Mode::Refresh),
+            (false, false) => Ok(Mode::Normal),
+        }
+    }
+}
+
+/// What is known about a cached response, stored next to its body.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+struct Meta {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Unix time the entry was last fetched or revalidated.
+    checked_at: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum Plan {
+    UseCached,
+    Request,
+    Unavailable,
+}
+
+fn plan(cached: Option<&Meta>, mode: Mode, now: u64) -> Plan {
+    match (cached, mode) {
+        (Some(_), Mode::Offline) => Plan::UseCached,
+        (None, Mode::Offline) => Plan::Unavailable,
+        (Some(meta), Mode::Normal) if now.saturating_sub(meta.checked_at) < TTL_SECS => Plan::UseCached,
+        _ => Plan::Request,
+    }
+}
+
+/// An HTTP cache for small JSON documents such as registry indexes and pack
+/// manifests, revalidated with ETag and Last-Modified.
+pub struct Cache {
+    dir: PathBuf,
+    mode: Mode,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf, mode: Mode) -> Cache {
+        Cache { dir, mode }
+    }
+
+    /// Returns the body at `url`, from the cache when it is fresh enough. If
+    /// the server can't be reached, a stale cached copy is used with a
+    /// warning.
+    pub fn get(
+        &self,
+        client: &reqwest::blocking::Client,
+        token: Option<&str>,
+        url: &str,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let (body_path, meta_path) = self.paths(url);
+        let cached = self.read(&body_path, &meta_path);
+        let now = crate::state::now();
+
+        match plan(cached.as_ref().map(|(m, _)| m), self.mode, now) {
+            Plan::UseCached => return Ok(cached.unwrap().1),
+            Plan::Unavailable => return Err(format!("{} is not cached (offline)", url).into()),
+            Plan::Request => {}
+        }
+
+        let mut request = client.get(url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        if let Some((meta, _)) = &cached {
+            if let Some(etag) = &meta.etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(modified) = &meta.last_modified {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, modified);
+            }
+        }
+
+        let response = match request.send().and_then(|r| r.error_for_status()) {
+            Ok(r) => r,
+            Err(e) => {
+                let Some((meta, body)) = cached else {
+                    return Err(e.into());
+                };
+                eprintln!(
+                    "hookplayer: warning: {}: {}; using cached copy from {} ago",
+                    url,
+                    e,
+                    describe_age(now.saturating_sub(meta.checked_at))
+                );
+                return Ok(body);
+            }
+        };
+
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED
+            && let Some((meta, body)) = cached
+        {
+            self.write_meta(&meta_path, &Meta { checked_at: now, ..meta });
+            return Ok(body);
+        }
+
+        let header = |name| {
+            response.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string)
+        };
+        let meta = Meta {
+            url: url.to_string(),
+            etag: header(reqwest::header::ETAG),
+            last_modified: header(reqwest::header::LAST_MODIFIED),
+            checked_at: now,
+        };
+        let body = response.bytes()?.to_vec();
+        if fsutil::write_atomic(&body_path, &body).is_ok() {
+            self.write_meta(&meta_path, &meta);
+        }
+        Ok(body)
+    }
+
+    /// The body and metadata files for `url`, named by a hash of the URL.
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let digest = Sha256::digest(url.as_bytes());
+        let name: String = digest[..12].iter().map(|b| format!("{:02x}", b)).collect();
+        (self.dir.join(format!("{}.body", name)), self.dir.join(format!("{}.json", name)))
+    }
+
+    fn read(&self, body_path: &Path, meta_path: &Path) -> Option<(Meta, Vec<u8>)> {
+        let meta = serde_json::from_str(&std::fs::read_to_string(meta_path).ok()?).ok()?;
+        let body = std::fs::read(body_path).ok()?;
+        Some((meta, body))
+    }
+
+    /// Failing to cache isn't worth failing the command over.
+    fn write_meta(&self, meta_path: &Path, meta: &Meta) {
+        if let Ok(json) = serde_json::to_string_pretty(meta) {
+            let _ = fsutil::write_atomic(meta_path, json.as_bytes());
+        }
+    }
+}
+
+fn describe_age(secs: u64) -> String {
+    match secs {
+        s if s < 120 => format!("{}s", s),
+        s if s < 2 * 3600 => format!("{}m", s / 60),
+        s if s < 2 * 86400 => format!("{}h", s / 3600),
+        s => format!("{}d", s / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn take_flags_strips_mode() {
+        let mut list = args(&["hookplayer", "list", "--offline"]);
+        assert_eq!(Mode::take_flags(&mut list), Ok(Mode::Offline));
+        assert_eq!(list, args(&["hookplayer", "list"]));
+        assert!(Mode::take_flags(&mut args(&["--offline", "--refresh"])).is_err());
+    }
+
+    #[test]
+    fn plan_follows_ttl_and_mode() {
+        let meta = Meta { checked_at: 1000, ..Default::default() };
+        assert_eq!(plan(Some(&meta), Mode::Normal, 1000 + TTL_SECS - 1), Plan::UseCached);
+        assert_eq!(plan(Some(&meta), Mode::Normal, 1000 + TTL_SECS), Plan::Request);
+        assert_eq!(plan(Some(&meta), Mode::Refresh, 1000), Plan::Request);
+        assert_eq!(plan(Some(&meta), Mode::Offline, u64::MAX), Plan::UseCached);
+        assert_eq!(plan(None, Mode::Normal, 0), Plan::Request);
+        assert_eq!(plan(None, Mode::Offline, 0), Plan::Unavailable);
+    }
+
+    #[test]
+    fn offline_reads_stored_entries_only() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cache = Cache::new(dir.path().to_path_buf(), Mode::Offline);
+        let client = reqwest::blocking::Client::new();
+        let url = "https://example.invalid/index.json";
+        assert!(cache.get(&client, None, url).is_err());
+
+        let (body_path, meta_path) = cache.paths(url);
+        fsutil::write_atomic(&body_path, b"{}").unwrap();
+        cache.write_meta(&meta_path, &Meta { url: url.into(), ..Default::default() });
+        assert_eq!(cache.get(&client, None, url).unwrap(), b"{}");
+    }
+
+    #[test]
+    fn describe_age_picks_units() {
+        assert_eq!(describe_age(59), "59s");
+        assert_eq!(describe_age(600), "10m");
+        assert_eq!(describe_age(5 * 3600), "5h");
+        assert_eq!(describe_age(3 * 86400), "3d");
+    }
+}
@@ -0,0 +1,303 @@
+use crate::config::{self, Config};
+use crate::player;
+use crate::settings;
+use crate::sounds::{self, Clip};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A single problem found by `hookplayer check`.
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    pub kind: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    /// The profile whose events hold `event`, if it isn't in [events].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// The `[sounds]` entry the problem is in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl Problem {
+    fn new(kind: &'static str, message: String) -> Self {
+        Problem { kind, message, event: None, profile: None, sound: None, path: None }
+    }
+
+    /// The config key the problem is under, e.g. `profiles.work.events.stop`.
+    fn key(&self) -> Option<String> {
+        match (&self.sound, &self.profile, &self.event) {
+            (Some(sound), _, _) => Some(format!("sounds.{}", sound)),
+            (None, Some(profile), Some(event)) => Some(format!("profiles.{}.events.{}", profile, event)),
+            (None, None, Some(event)) => Some(format!("events.{}", event)),
+            _ => None,
+        }
+    }
+}
+
+/// Where a sound is referenced from, for the problems found in it.
+#[derive(Default)]
+struct Site {
+    event: Option<String>,
+    profile: Option<String>,
+    sound: Option<String>,
+}
+
+impl Site {
+    fn problem(&self, kind: &'static str, message: String) -> Problem {
+        Problem {
+            event: self.event.clone(),
+            profile: self.profile.clone(),
+            sound: self.sound.clone(),
+            ..Problem::new(kind, message)
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    config: String,
+    ok: bool,
+    problems: &'a [Problem],
+}
+
+/// Validates the effective settings (config file plus any project file,
+/// environment and flag overrides) and every sound they reference.
+/// Prints a report and returns true if no problems were found.
+pub fn run(json: bool, cli: &toml::Table) -> Result<bool, Box<dyn std::error::Error>> {
+    // A missing config.toml is fine: every key has a default.
+    let path = config::config_path()?;
+    let problems = match settings::load(cli) {
+        Ok((cfg, warnings)) => check_config(&cfg, &warnings),
+        Err(e) => vec![Problem::new("parse", e.to_string())],
+    };
+
+    if json {
+        let report = Report {
+            config: path.display().to_string(),
+            ok: problems.is_empty(),
+            problems: &problems,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        let mut name = path.display().to_string();
+        if !path.is_file() {
+            name += " (not found, using defaults)";
+        }
+        if problems.is_empty() {
+            println!("{}: ok", name);
+        } else {
+            println!("{}: {} problem(s)", name, problems.len());
+        }
+        for p in &problems {
+            match p.key() {
+                Some(key) => println!("  [{}] {}: {}", p.kind, key, p.message),
+                None => println!("  [{}] {}", p.kind, p.message),
+            }
+        }
+    }
+
+    Ok(problems.is_empty())
+}
+
+/// Checks a loaded config, and the unknown-key warnings from loading it,
+/// against the filesystem.
+pub fn check_config(cfg: &Config, warnings: &[String]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    for warning in warnings {
+        problems.push(Problem::new("unknown_key", warning.clone()));
+    }
+
+    // Loading already rejects a volume out of range.
+    if cfg.volume == 0.0 {
+        problems.push(Problem::new("volume", "volume is 0, nothing will be audible".into()));
+    }
+
+    if cfg.events.is_empty() {
+        problems.push(Problem::new("no_events", "no events configured".into()));
+    }
+
+    check_events(cfg, &cfg.events, None, &mut problems);
+
+    let mut profiles: Vec<&String> = cfg.profiles.keys().collect();
+    profiles.sort();
+    for name in profiles {
+        check_events(cfg, &cfg.profiles[name].events, Some(name), &mut problems);
+    }
+
+    let mut names: Vec<&String> = cfg.sounds.keys().collect();
+    names.sort();
+    for name in names {
+        let site = Site { sound: Some(name.clone()), ..Default::default() };
+        let clips = cfg.expand(&[format!("@{}", name)]);
+        check_sound(cfg, &site, &cfg.sounds[name].file, &clips, &clips, &mut problems);
+    }
+
+    problems
+}
+
+/// Checks the sound lists of [events], or of a profile's events.
+fn check_events(
+    cfg: &Config,
+    events: &HashMap<String, Vec<String>>,
+    profile: Option<&String>,
+    problems: &mut Vec<Problem>,
+) {
+    let mut names: Vec<&String> = events.keys().collect();
+    names.sort();
+
+    for event in names {
+        let site = Site { event: Some(event.clone()), profile: profile.cloned(), ..Default::default() };
+        let sounds = &events[event];
+        if sounds.is_empty() {
+            problems.push(site.problem("empty_event", "no sounds listed".into()));
+        }
+        let included = cfg.expand(sounds);
+        // `@name` files are checked once, with the [sounds] entries.
+        for sound in sounds.iter().filter(|s| !s.starts_with(['!', '@'])) {
+            let matched = cfg.expand(std::slice::from_ref(sound));
+            check_sound(cfg, &site, sound, &matched, &included, problems);
+        }
+    }
+}
+
+/// Checks the files one entry matched, skipping those that `included`
+/// (the whole list, after exclusions) leaves out.
+fn check_sound(
+    cfg: &Config,
+    site: &Site,
+    sound: &str,
+    matched: &[Clip],
+    included: &[Clip],
+    problems: &mut Vec<Problem>,
+) {
+    if matched.is_empty() {
+        problems.push(site.problem("empty_pattern", format!("{} matches no sound files", sound)));
+    }
+    let base = cfg.sounds_dir_abs();
+    for clip in matched.iter().filter(|c| included.contains(c)) {
+        if let Err(e) = sounds::fetch(clip) {
+            problems.push(site.problem("fetch_failed", format!("{} could not be fetched: {}", sound, e)));
+            continue;
+        }
+        let rel = clip.path.strip_prefix(&base).unwrap_or(&clip.path);
+        problems.extend(check_file(&clip.path, &rel.to_string_lossy(), site));
+    }
+}
+
+/// Confirms a referenced file exists and can be decoded.
+fn check_file(path: &Path, sound: &str, site: &Site) -> Option<Problem> {
+    let problem = if !path.is_file() {
+        site.problem("missing_file", format!("{} not found", sound))
+    } else if let Err(e) = player::probe(path) {
+        site.problem("unsupported_codec", format!("{} could not be decoded: {}", sound, e))
+    } else {
+        return None;
+    };
+    Some(Problem { path: Some(path.display().to_string()), ..problem })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn write_wav(path: &Path) {
+        fs::write(path, player::silent_wav(400)).unwrap();
+    }
+
+    fn check(dir: &TempDir, toml_src: &str) -> Vec<Problem> {
+        let src = toml_src.replace("SOUNDS", &dir.path().display().to_string());
+        let (cfg, warnings) = config::parse(Path::new("config.toml"), &src).unwrap();
+        check_config(&cfg, &warnings)
+    }
+
+    fn kinds(problems: &[Problem]) -> Vec<&'static str> {
+        problems.iter().map(|p| p.kind).collect()
+    }
+
+    #[test]
+    fn valid_config_has_no_problems() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("pack")).unwrap();
+        write_wav(&dir.path().join("pack/ok.wav"));
+
+        let problems = check(
+            &dir,
+            "sounds_dir = \"SOUNDS\"\nvolume = 0.5\n[events]\nstart = [\"pack/ok.wav\"]\n",
+        );
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn flags_missing_and_undecodable_files() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("pack")).unwrap();
+        fs::write(dir.path().join("pack/bad.mp3"), "<html>404</html>").unwrap();
+
+        let problems = check(
+            &dir,
+            "sounds_dir = \"SOUNDS\"\nvolume = 0.5\n[events]\nstart = [\"pack/typo.mp3\", \"pack/bad.mp3\"]\n",
+        );
+        assert_eq!(kinds(&problems), vec!["missing_file", "unsupported_codec"]);
+        assert_eq!(problems[0].event.as_deref(), Some("start"));
+    }
+
+    #[test]
+    fn flags_unknown_keys_and_empty_events() {
+        let dir = TempDir::new().unwrap();
+        let problems = check(
+            &dir,
+            "sounds_dir = \"SOUNDS\"\nvolume = 0.5\nvolumme = 0.2\n[events]\nstop = []\n",
+        );
+        assert_eq!(kinds(&problems), vec!["unknown_key", "empty_event"]);
+    }
+
+    #[test]
+    fn checks_files_matched_by_patterns() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("pack")).unwrap();
+        write_wav(&dir.path().join("pack/ok.wav"));
+        fs::write(dir.path().join("pack/bad.mp3"), "not audio").unwrap();
+
+        let problems = check(
+            &dir,
+            "sounds_dir = \"SOUNDS\"\n[events]\nstart = [\"pack/\", \"other/*.mp3\"]\nstop = [\"pack/\", \"!pack/bad.mp3\"]\n",
+        );
+        assert_eq!(kinds(&problems), vec!["unsupported_codec", "empty_pattern"]);
+        assert_eq!(problems[0].event.as_deref(), Some("start"));
+    }
+
+    #[test]
+    fn checks_profile_events_and_library_entries() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("pack")).unwrap();
+        write_wav(&dir.path().join("pack/ok.wav"));
+
+        let problems = check(
+            &dir,
+            "sounds_dir = \"SOUNDS\"\n[events]\nstart = [\"pack/ok.wav\"]\n\
+             [profiles.work.events]\nstop = [\"pack/typo.wav\"]\n[sounds]\nding = \"pack/gone.wav\"\n",
+        );
+        assert_eq!(kinds(&problems), vec!["missing_file", "missing_file"]);
+        assert_eq!(problems[0].key().as_deref(), Some("profiles.work.events.stop"));
+        assert_eq!(problems[1].key().as_deref(), Some("sounds.ding"));
+    }
+
+    #[test]
+    fn flags_zero_volume_and_no_events() {
+        let cfg = Config {
+            sounds_dir: "/nonexistent".into(),
+            volume: 0.0,
+            ..Default::default()
+        };
+        let problems = check_config(&cfg, &[]);
+        assert_eq!(kinds(&problems), vec!["volume", "no_events"]);
+    }
+}
@@ -1,104 +1,1100 @@
-use serde::Deserialize;
+use crate::fsutil;
+use crate::fuzzy;
+use crate::player::{self, Gain, Output};
+use crate::registry;
+use crate::schedule;
+use crate::sounds;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Deserialize)]
+/// Top-level keys understood by `Config`. Anything else in config.toml is
+/// ignored on load and reported by `hookplayer check`.
+pub const KNOWN_KEYS: &[&str] = &[
+    "version", "include", "sounds_dir", "volume", "events", "fallbacks", "sounds", "profile", "profiles", "schedule",
+    "event_volume", "mute", "device", "backend", "registry_url",
+    "registries", "github_token", "download_jobs",
+];
+
+/// The config schema version this build writes: one past the last step in
+/// `MIGRATIONS`. Older files are upgraded by `migrate` when they are read.
+pub const CONFIG_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+const DEFAULT_SOUNDS_DIR: &str = "~/.config/hookplayer/sounds";
+const DEFAULT_VOLUME: f32 = 0.5;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Config {
+    /// Schema version; see `CONFIG_VERSION`.
+    pub version: u32,
+    /// Other config files (paths or globs) applied before this one.
+    pub include: Vec<String>,
     pub sounds_dir: String,
+    /// Master volume. Like every volume and gain in the config it may be
+    /// written as a factor (`0.5`), a percentage (`"50%"`) or in dB (`"-6dB"`).
+    #[serde(deserialize_with = "de_level")]
     pub volume: f32,
     pub events: HashMap<String, Vec<String>>,
+    /// Per-event volume factors, applied to an event and its dotted children.
+    #[serde(deserialize_with = "de_level_map")]
+    pub event_volume: HashMap<String, f32>,
+    /// Silences every event, e.g. from `HOOKPLAYER_MUTE=1` in a CI job.
+    pub mute: bool,
+    /// Output device name; the system default when unset.
+    pub device: Option<String>,
+    /// Audio host to play through (e.g. `alsa`, `jack`); the default when unset.
+    pub backend: Option<String>,
+    /// The default pack registry; empty to use only `registries`.
+    pub registry_url: String,
+    /// Extra pack registries, searched after the default one.
+    pub registries: Vec<RegistrySource>,
+    /// Token for packs hosted in private GitHub repos. Best set through
+    /// `GITHUB_TOKEN` or `HOOKPLAYER_GITHUB_TOKEN` rather than in a file.
+    pub github_token: Option<String>,
+    /// How many pack files to download at once.
+    pub download_jobs: usize,
+    /// Events to try, in order, when an event has no sounds of its own.
+    pub fallbacks: HashMap<String, Vec<String>>,
+    /// Named sounds that event lists can reference as `@name`.
+    pub sounds: HashMap<String, SoundDef>,
+    /// The profile applied on top of the settings above, if any.
+    pub profile: Option<String>,
+    pub profiles: HashMap<String, Profile>,
+    /// Named time windows that mute, lower the volume or switch profile.
+    pub schedule: HashMap<String, ScheduleWindow>,
 }
 
-impl Config {
-    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
-        let path = config_path()?;
-        let raw = std::fs::read_to_string(&path)?;
-        Ok(toml::from_str(&raw)?)
+/// A named set of overrides, selected with `profile = "<name>"` or by a
+/// schedule window.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Multiplies the master volume while the profile is active.
+    #[serde(deserialize_with = "de_opt_level")]
+    pub volume: Option<f32>,
+    /// Replaces the sound lists of the events it names.
+    pub events: HashMap<String, Vec<String>>,
+}
+
+/// A pack registry index, e.g. `{ name = "team", url = "https://..." }`.
+/// The name namespaces its packs when they collide with another registry's.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RegistrySource {
+    pub name: String,
+    pub url: String,
+}
+
+/// A recurring local-time window, e.g. `from = "22:00"`, `to = "07:00"`.
+/// A window whose `to` is earlier than its `from` runs past midnight and
+/// belongs to the day it starts on.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScheduleWindow {
+    /// Weekdays the window applies to (`mon` .. `sun`); empty means every day.
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub mute: bool,
+    /// Caps the output volume while the window is active.
+    #[serde(default, deserialize_with = "de_opt_level")]
+    pub volume: Option<f32>,
+    pub profile: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            version: CONFIG_VERSION,
+            include: Vec::new(),
+            sounds_dir: DEFAULT_SOUNDS_DIR.to_string(),
+            volume: DEFAULT_VOLUME,
+            events: HashMap::new(),
+            event_volume: HashMap::new(),
+            mute: false,
+            device: None,
+            backend: None,
+            registry_url: registry::REGISTRY_URL.to_string(),
+            registries: Vec::new(),
+            github_token: None,
+            download_jobs: 4,
+            fallbacks: HashMap::new(),
+            sounds: HashMap::new(),
+            profile: None,
+            profiles: HashMap::new(),
+            schedule: HashMap::new(),
+        }
+    }
+}
+
+/// An entry in the `[sounds]` library. Written either as a bare path
+/// (`ding = "pack/ping.wav"`) or as a table with playback options
+/// (`ding = { file = "pack/ping.wav", gain = 0.8, start = 0.1, end = 1.5 }`).
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(from = "SoundDefRepr")]
+pub struct SoundDef {
+    pub file: String,
+    /// Linear gain applied on top of the master volume.
+    pub gain: f32,
+    /// Seconds to skip at the start of the file.
+    pub start: Option<f32>,
+    /// Position in seconds at which to stop playing.
+    pub end: Option<f32>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SoundDefRepr {
+    File(String),
+    Detailed {
+        file: String,
+        #[serde(default = "unity_gain", deserialize_with = "de_level")]
+        gain: f32,
+        start: Option<f32>,
+        end: Option<f32>,
+    },
+}
+
+fn unity_gain() -> f32 {
+    1.0
+}
+
+/// A volume or gain as written in the config; see `player::parse_level`.
+struct Level(f32);
+
+impl<'de> Deserialize<'de> for Level {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        struct Visitor;
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = Level;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a volume like 0.5, \"50%\" or \"-6dB\"")
+            }
+            fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Level, E> {
+                Ok(Level(v as f32))
+            }
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Level, E> {
+                Ok(Level(v as f32))
+            }
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Level, E> {
+                Ok(Level(v as f32))
+            }
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Level, E> {
+                player::parse_level(v).map(Level).map_err(E::custom)
+            }
+        }
+        d.deserialize_any(Visitor)
+    }
+}
+
+fn de_level<'de, D: serde::Deserializer<'de>>(d: D) -> Result<f32, D::Error> {
+    Level::deserialize(d).map(|l| l.0)
+}
+
+fn de_opt_level<'de, D: serde::Deserializer<'de>>(d: D) -> Result<Option<f32>, D::Error> {
+    Option::<Level>::deserialize(d).map(|l| l.map(|l| l.0))
+}
+
+fn de_level_map<'de, D: serde::Deserializer<'de>>(d: D) -> Result<HashMap<String, f32>, D::Error> {
+    HashMap::<String, Level>::deserialize(d).map(|m| m.into_iter().map(|(k, l)| (k, l.0)).collect())
+}
+
+impl From<SoundDefRepr> for SoundDef {
+    fn from(repr: SoundDefRepr) -> Self {
+        match repr {
+            SoundDefRepr::File(file) => SoundDef { file, gain: 1.0, start: None, end: None },
+            SoundDefRepr::Detailed { file, gain, start, end } => SoundDef { file, gain, start, end },
+        }
     }
+}
 
+/// A config file that could not be read or parsed, with enough context to
+/// point the user at the offending line.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub message: String,
+    /// 1-based line and column of the error, when known.
+    pub location: Option<(usize, usize)>,
+    pub snippet: Option<String>,
+}
+
+impl ConfigError {
+    fn from_toml(path: &Path, raw: &str, err: toml::de::Error) -> Self {
+        let location = err.span().map(|span| line_col(raw, span.start));
+        let snippet = location.and_then(|(line, col)| {
+            let text = raw.lines().nth(line - 1)?;
+            let gutter = " ".repeat(line.to_string().len());
+            Some(format!(
+                "{gutter} |\n{line} | {text}\n{gutter} | {}^",
+                " ".repeat(col - 1)
+            ))
+        });
+        ConfigError {
+            path: path.to_path_buf(),
+            message: err.message().to_string(),
+            location,
+            snippet,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some((line, col)) = self.location {
+            write!(f, ":{}:{}", line, col)?;
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(snippet) = &self.snippet {
+            write!(f, "\n{}", snippet)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
     pub fn sounds_dir_abs(&self) -> PathBuf {
-        // HOOKPLAYER_SOUNDS_DIR env var overrides config
-        let raw = std::env::var("HOOKPLAYER_SOUNDS_DIR")
-            .unwrap_or_else(|_| self.sounds_dir.clone());
-        expand_tilde(&raw)
+        expand_tilde(&self.sounds_dir)
     }
 
-    pub fn sounds_for_event(&self, event: &str) -> Vec<PathBuf> {
-        let base = self.sounds_dir_abs();
-        let key = if self.events.contains_key(event) {
-            event
-        } else {
-            "unknown"
-        };
-        self.events
-            .get(key)
-            .cloned()
-            .unwrap_or_default()
+    /// Every registry to search, the default one (named `default`) first.
+    pub fn registry_sources(&self) -> Vec<RegistrySource> {
+        let default = (!self.registry_url.is_empty()).then(|| RegistrySource {
+            name: registry::DEFAULT_REGISTRY.to_string(),
+            url: self.registry_url.clone(),
+        });
+        default.into_iter().chain(self.registries.iter().cloned()).collect()
+    }
+
+    /// The audio output selected by `backend` and `device`.
+    pub fn output(&self) -> Output {
+        Output { backend: self.backend.clone(), device: self.device.clone() }
+    }
+
+    pub fn sounds_for_event(&self, event: &str) -> Vec<sounds::Clip> {
+        match self.resolve_event(event) {
+            Some(key) => self.expand(&self.events[&key]),
+            None => Vec::new(),
+        }
+    }
+
+    /// Expands a list of event entries into clips.
+    pub fn expand(&self, entries: &[String]) -> Vec<sounds::Clip> {
+        sounds::expand(&self.sounds_dir_abs(), entries, &self.sounds)
+    }
+
+    /// Returns the first event in the fallback chain that is configured.
+    pub fn resolve_event(&self, event: &str) -> Option<String> {
+        self.fallback_chain(event)
+            .into_iter()
+            .find(|e| self.events.contains_key(e))
+    }
+
+    /// Lists the events tried for `event`, in order: the event itself, its
+    /// explicit `[fallbacks]`, its parent (`task.error` for `task.error.build`),
+    /// and finally `unknown`. Each fallback and parent is expanded the same way.
+    pub fn fallback_chain(&self, event: &str) -> Vec<String> {
+        fn visit(cfg: &Config, event: &str, chain: &mut Vec<String>) {
+            if chain.iter().any(|e| e == event) {
+                return;
+            }
+            chain.push(event.to_string());
+            for next in cfg.fallbacks.get(event).into_iter().flatten() {
+                visit(cfg, next, chain);
+            }
+            if let Some(parent) = parent_event(event) {
+                visit(cfg, parent, chain);
+            }
+        }
+
+        let mut chain = Vec::new();
+        visit(self, event, &mut chain);
+        if !chain.iter().any(|e| e == "unknown") {
+            chain.push("unknown".to_string());
+        }
+        chain
+    }
+
+    /// Rejects settings that would make event resolution ill-defined.
+    fn validate(&self) -> Result<(), String> {
+        if self.version > CONFIG_VERSION {
+            return Err(newer_version(self.version, CONFIG_VERSION));
+        }
+        if let Some(cycle) = self.find_fallback_cycle() {
+            return Err(format!("fallback cycle: {}", cycle.join(" -> ")));
+        }
+        check_volume(self.volume).map_err(|e| format!("volume: {}", e))?;
+
+        let mut names: Vec<&String> = self.sounds.keys().collect();
+        names.sort();
+        for name in &names {
+            let def = &self.sounds[*name];
+            if !def.gain.is_finite() || def.gain < 0.0 {
+                return Err(format!("sounds.{}: gain must be a non-negative number", name));
+            }
+            for (key, value) in [("start", def.start), ("end", def.end)] {
+                if let Some(v) = value
+                    && (!v.is_finite() || v < 0.0)
+                {
+                    return Err(format!("sounds.{}: {} must be a non-negative number of seconds", name, key));
+                }
+            }
+            if let (Some(start), Some(end)) = (def.start, def.end)
+                && start >= end
+            {
+                return Err(format!("sounds.{}: start ({}) must be before end ({})", name, start, end));
+            }
+        }
+
+        for name in &names {
+            sounds::validate_entry(&self.sounds[*name].file).map_err(|e| format!("sounds.{}: {}", name, e))?;
+        }
+
+        self.validate_events("events", &self.events)?;
+
+        if !(1..=32).contains(&self.download_jobs) {
+            return Err(format!("download_jobs must be between 1 and 32, got {}", self.download_jobs));
+        }
+
+        let mut seen = Vec::new();
+        for source in &self.registries {
+            let name = source.name.as_str();
+            if name.is_empty() || name.contains(['/', ' ']) {
+                return Err(format!("registries: invalid name '{}' (use letters, digits, - or _)", name));
+            }
+            if name == registry::DEFAULT_REGISTRY || name == registry::LOCAL_REGISTRY {
+                return Err(format!("registries: the name '{}' is reserved", name));
+            }
+            if seen.contains(&name) {
+                return Err(format!("registries: name '{}' is used more than once", name));
+            }
+            seen.push(name);
+        }
+
+        let mut events: Vec<(&String, &f32)> = self.event_volume.iter().collect();
+        events.sort_by_key(|(event, _)| *event);
+        for (event, v) in events {
+            if !v.is_finite() || *v < 0.0 {
+                return Err(format!("event_volume.{}: must be a non-negative number", event));
+            }
+        }
+
+        let mut profiles: Vec<&String> = self.profiles.keys().collect();
+        profiles.sort();
+        for name in profiles {
+            let profile = &self.profiles[name];
+            if let Some(v) = profile.volume {
+                check_volume(v).map_err(|e| format!("profiles.{}.volume: {}", name, e))?;
+            }
+            self.validate_events(&format!("profiles.{}.events", name), &profile.events)?;
+        }
+        if let Some(name) = &self.profile {
+            self.check_profile_exists(name).map_err(|e| format!("profile: {}", e))?;
+        }
+
+        let mut windows: Vec<&String> = self.schedule.keys().collect();
+        windows.sort();
+        for name in windows {
+            let window = &self.schedule[name];
+            let prefix = format!("schedule.{}", name);
+            schedule::validate_window(window).map_err(|e| format!("{}: {}", prefix, e))?;
+            if let Some(v) = window.volume {
+                check_volume(v).map_err(|e| format!("{}.volume: {}", prefix, e))?;
+            }
+            if let Some(p) = &window.profile {
+                self.check_profile_exists(p).map_err(|e| format!("{}.profile: {}", prefix, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks every entry of an event map, including `@name` references.
+    fn validate_events(&self, prefix: &str, events: &HashMap<String, Vec<String>>) -> Result<(), String> {
+        let aliases: Vec<&str> = self.sounds.keys().map(String::as_str).collect();
+        let mut names: Vec<&String> = events.keys().collect();
+        names.sort();
+        for event in names {
+            for entry in &events[event] {
+                sounds::validate_entry(entry).map_err(|e| format!("{}.{}: {}", prefix, event, e))?;
+                let Some(alias) = entry.trim_start_matches('!').strip_prefix('@') else {
+                    continue;
+                };
+                if !self.sounds.contains_key(alias) {
+                    let hint = fuzzy::closest(alias, &aliases)
+                        .map(|a| format!(" (did you mean '@{}'?)", a))
+                        .unwrap_or_default();
+                    return Err(format!("{}.{}: unknown sound '@{}'{}", prefix, event, alias, hint));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_profile_exists(&self, name: &str) -> Result<(), String> {
+        if self.profiles.contains_key(name) {
+            return Ok(());
+        }
+        let names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+        let hint = fuzzy::closest(name, &names)
+            .map(|p| format!(" (did you mean '{}'?)", p))
+            .unwrap_or_default();
+        Err(format!("unknown profile '{}'{}", name, hint))
+    }
+
+    /// Applies a profile's event overrides to this config and makes it the
+    /// active profile, whose volume then feeds into `gain`.
+    pub fn apply_profile(&mut self, name: &str) -> Result<(), String> {
+        self.check_profile_exists(name)?;
+        let profile = self.profiles[name].clone();
+        self.events.extend(profile.events);
+        self.profile = Some(name.to_string());
+        Ok(())
+    }
+
+    /// The gain pipeline for an event, without a schedule cap. The event
+    /// factor comes from the event or its nearest dotted parent in
+    /// `[event_volume]`.
+    pub fn gain(&self, event: &str) -> Gain {
+        let profile = self
+            .profile
+            .as_ref()
+            .and_then(|p| self.profiles.get(p))
+            .and_then(|p| p.volume)
+            .unwrap_or(1.0);
+        let mut scope = Some(event);
+        let mut factor = 1.0;
+        while let Some(e) = scope {
+            if let Some(v) = self.event_volume.get(e) {
+                factor = *v;
+                break;
+            }
+            scope = parent_event(e);
+        }
+        Gain { master: self.volume, profile, event: factor, cap: None }
+    }
+
+    /// Finds a cycle through `[fallbacks]` and parent links, if any.
+    fn find_fallback_cycle(&self) -> Option<Vec<String>> {
+        fn walk(cfg: &Config, event: &str, stack: &mut Vec<String>, done: &mut Vec<String>) -> Option<Vec<String>> {
+            if let Some(pos) = stack.iter().position(|e| e == event) {
+                let mut cycle = stack[pos..].to_vec();
+                cycle.push(event.to_string());
+                return Some(cycle);
+            }
+            if done.iter().any(|e| e == event) {
+                return None;
+            }
+            stack.push(event.to_string());
+            let nexts = cfg.fallbacks.get(event).into_iter().flatten().map(String::as_str);
+            for next in nexts.chain(parent_event(event)) {
+                if let Some(cycle) = walk(cfg, next, stack, done) {
+                    return Some(cycle);
+                }
+            }
+            stack.pop();
+            done.push(event.to_string());
+            None
+        }
+
+        let mut starts: Vec<&String> = self.fallbacks.keys().collect();
+        starts.sort();
+        let mut done = Vec::new();
+        starts
             .into_iter()
-            .map(|f| base.join(f))
-            .collect()
+            .find_map(|e| walk(self, e, &mut Vec::new(), &mut done))
+    }
+}
+
+fn check_volume(v: f32) -> Result<(), String> {
+    if v.is_finite() && (0.0..=1.0).contains(&v) {
+        Ok(())
+    } else {
+        Err(format!("{} is out of range (expected 0.0 to 1.0)", v))
+    }
+}
+
+/// `task.error.build` -> `task.error` -> `task` -> None.
+fn parent_event(event: &str) -> Option<&str> {
+    event.rsplit_once('.').map(|(parent, _)| parent)
+}
+
+/// Parses config source read from `path`, returning the config and a warning
+/// for each unknown key.
+pub fn parse(path: &Path, raw: &str) -> Result<(Config, Vec<String>), ConfigError> {
+    let (table, warnings) = parse_table(path, raw)?;
+    Ok((from_table(path, table)?, warnings))
+}
+
+/// Parses one layer of settings and checks its values' types, without the
+/// cross-key validation that only makes sense once layers are merged.
+pub fn parse_table(path: &Path, raw: &str) -> Result<(toml::Table, Vec<String>), ConfigError> {
+    let table: toml::Table =
+        toml::from_str(raw).map_err(|e| ConfigError::from_toml(path, raw, e))?;
+    toml::from_str::<Config>(raw).map_err(|e| ConfigError::from_toml(path, raw, e))?;
+    let warnings = unknown_key_warnings(&table);
+    Ok((table, warnings))
+}
+
+/// Builds and validates a config from a (possibly merged) table. Errors are
+/// reported against `path`.
+pub fn from_table(path: &Path, table: toml::Table) -> Result<Config, ConfigError> {
+    let error = |message| ConfigError { path: path.to_path_buf(), message, location: None, snippet: None };
+    let cfg: Config = toml::Value::Table(table).try_into().map_err(|e: toml::de::Error| error(e.message().to_string()))?;
+    cfg.validate().map_err(error)?;
+    Ok(cfg)
+}
+
+/// Describes each top-level key that Config does not use, suggesting the
+/// known key it was probably meant to be.
+pub fn unknown_key_warnings(raw: &toml::Table) -> Vec<String> {
+    raw.keys()
+        .filter(|k| !KNOWN_KEYS.contains(&k.as_str()))
+        .map(|k| match fuzzy::closest(k, KNOWN_KEYS) {
+            Some(known) => format!("unknown key '{}' (did you mean '{}'?)", k, known),
+            None => format!("unknown key '{}'", k),
+        })
+        .collect()
+}
+
+/// One schema upgrade. It edits the document in place so comments and
+/// formatting survive.
+pub type Step = fn(&mut toml_edit::DocumentMut);
+
+/// Migrations between schema versions: `MIGRATIONS[i]` upgrades a version
+/// `i + 1` document to version `i + 2`. Version 1 is the layout hookplayer
+/// has always read, so there is nothing to upgrade yet.
+pub const MIGRATIONS: &[Step] = &[];
+
+/// The result of upgrading a config source.
+pub struct Migration {
+    pub from: u32,
+    pub to: u32,
+    pub output: String,
+}
+
+/// Upgrades a config source written for an older schema by running the
+/// `steps` it hasn't had yet, normally `MIGRATIONS`. A file without a
+/// `version` key is version 1. Returns None when the source is already
+/// current, or can't be parsed (the caller's parse reports why).
+pub fn migrate(path: &Path, raw: &str, steps: &[Step]) -> Result<Option<Migration>, ConfigError> {
+    let current = steps.len() as u32 + 1;
+    let Ok(mut doc) = raw.parse::<toml_edit::DocumentMut>() else {
+        return Ok(None);
+    };
+    let from = match doc.get("version") {
+        None => 1,
+        Some(v) => match v.as_integer() {
+            Some(v) if v >= 1 => v as u32,
+            _ => return Ok(None),
+        },
+    };
+    if from > current {
+        return Err(ConfigError {
+            path: path.to_path_buf(),
+            message: newer_version(from, current),
+            location: None,
+            snippet: None,
+        });
+    }
+    if from == current {
+        return Ok(None);
+    }
+
+    for step in &steps[from as usize - 1..] {
+        step(&mut doc);
+    }
+    match doc.get_mut("version").and_then(|v| v.as_value_mut()) {
+        Some(v) => {
+            let decor = v.decor().clone();
+            *v = toml_edit::Value::from(current as i64);
+            *v.decor_mut() = decor;
+        }
+        None => {
+            doc.insert("version", toml_edit::value(current as i64));
+        }
+    }
+    Ok(Some(Migration { from, to: current, output: doc.to_string() }))
+}
+
+fn newer_version(version: u32, supported: u32) -> String {
+    format!(
+        "config version {} is newer than this hookplayer supports ({}); run 'hookplayer update'",
+        version, supported
+    )
+}
+
+/// Reads the config file, upgrading it on disk first if it was written for
+/// an older schema. The original is backed up like any other edit, so
+/// `restore-backup` can put it back.
+pub fn read_config(path: &Path) -> Result<String, ConfigError> {
+    let raw = std::fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+    if migrate(path, &raw, MIGRATIONS)?.is_none() {
+        return Ok(raw);
+    }
+    let locks = locks_dir().map_err(|e| io_error(path, e))?;
+    let _lock = fsutil::lock(path, &locks).map_err(|e| io_error(path, e))?;
+    read_config_locked(path)
+}
+
+/// `read_config` for callers already holding the config lock. The file is
+/// read again here, as another process may have upgraded it meanwhile.
+fn read_config_locked(path: &Path) -> Result<String, ConfigError> {
+    let backups = backups_dir().map_err(|e| io_error(path, e))?;
+    // Only rewrite the file if the upgraded config is one we can load.
+    upgrade_file(path, MIGRATIONS, &backups, |output| parse(path, output).map(|_| ()))
+}
+
+/// Reads `path` and, if `steps` upgrade it and `check` accepts the result,
+/// backs it up into `backups` and writes the upgraded source back.
+fn upgrade_file(
+    path: &Path,
+    steps: &[Step],
+    backups: &Path,
+    check: impl Fn(&str) -> Result<(), ConfigError>,
+) -> Result<String, ConfigError> {
+    let raw = std::fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+    let Some(migration) = migrate(path, &raw, steps)? else {
+        return Ok(raw);
+    };
+    check(&migration.output)?;
+
+    let backup = fsutil::backup(path, backups).map_err(|e| io_error(path, e))?;
+    fsutil::write_atomic(path, migration.output.as_bytes()).map_err(|e| io_error(path, e))?;
+    eprintln!(
+        "hookplayer: upgraded {} from version {} to {}{}",
+        path.display(),
+        migration.from,
+        migration.to,
+        backup.map(|b| format!(" (original saved as {})", b.display())).unwrap_or_default()
+    );
+    Ok(migration.output)
+}
+
+fn io_error(path: &Path, e: impl std::fmt::Display) -> ConfigError {
+    ConfigError { path: path.to_path_buf(), message: e.to_string(), location: None, snippet: None }
+}
+
+/// A unified diff of two texts, line by line, with three lines of context.
+pub fn diff(old_name: &str, old: &str, new_name: &str, new: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+
+    // Longest common subsequence table, filled from the end.
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
     }
+
+    // Walk it into a list of (tag, old line, new line, text).
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push((' ', i, j, a[i]));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(('-', i, j, a[i]));
+            i += 1;
+        } else {
+            ops.push(('+', i, j, b[j]));
+            j += 1;
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let changed: Vec<usize> = (0..ops.len()).filter(|&k| ops[k].0 != ' ').collect();
+    let mut k = 0;
+    while k < changed.len() {
+        let start = changed[k].saturating_sub(3);
+        let mut end = changed[k];
+        while k < changed.len() && changed[k] <= end + 6 {
+            end = changed[k];
+            k += 1;
+        }
+        let end = (end + 4).min(ops.len());
+        let hunk = &ops[start..end];
+        let old_len = hunk.iter().filter(|op| op.0 != '+').count();
+        let new_len = hunk.iter().filter(|op| op.0 != '-').count();
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            hunk[0].1 + 1,
+            old_len,
+            hunk[0].2 + 1,
+            new_len
+        ));
+        for (tag, _, _, text) in hunk {
+            out.push_str(&format!("{}{}\n", tag, text));
+        }
+    }
+    out
+}
+
+/// Converts a byte offset into a 1-based (line, column) pair.
+fn line_col(raw: &str, offset: usize) -> (usize, usize) {
+    let before = &raw[..offset.min(raw.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+    (line, col)
 }
 
 /// Updates sounds_dir in the config file and returns the resolved path.
 pub fn set_sounds_dir(new_path: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    set("sounds_dir", new_path)?;
+    Ok(expand_tilde(new_path))
+}
+
+/// Returns the value of a dotted key (e.g. `events.notify`) as written in the
+/// config file, or its default when unset. Strings are returned unquoted.
+pub fn get(key: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let raw = read_config(&config_path()?)?;
+    let table: toml::Table = toml::from_str(&raw)?;
+    let defaults = toml::Table::try_from(Config::default())?;
+    let path = parse_key(key)?;
+
+    let found = lookup(&table, &path).or_else(|| lookup(&defaults, &path));
+    Ok(found.map(|v| match v {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Table(t) => toml::to_string(t).unwrap_or_default().trim_end().to_string(),
+        other => other.to_string(),
+    }))
+}
+
+/// The sounds config.toml itself lists for `event`, leaving out includes,
+/// project files and the environment. None if it doesn't set the event.
+pub fn get_event(event: &str) -> Result<Option<Vec<String>>, Box<dyn std::error::Error>> {
+    let raw = read_config(&config_path()?)?;
+    let table: toml::Table = toml::from_str(&raw)?;
+    match lookup(&table, &["events".to_string(), event.to_string()]) {
+        Some(value) => Ok(Some(value.clone().try_into()?)),
+        None => Ok(None),
+    }
+}
+
+/// Sets a dotted key in the config file, keeping the rest of the file intact.
+/// The value is parsed as TOML when possible and as a plain string otherwise.
+pub fn set(key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+    edit_config(|path, raw| set_in(path, raw, key, value).map(Some))?;
+    Ok(())
+}
+
+/// Removes a dotted key from the config file. Returns false if it was not set.
+pub fn unset(key: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    edit_config(|path, raw| unset_in(path, raw, key))
+}
+
+/// Runs a read-modify-write cycle on the config file while holding its
+/// lock, so concurrent hookplayer runs can't lose each other's changes. `f`
+/// returns the new source, or None to leave the file alone. The previous
+/// version is backed up first. Returns whether `f` made a change.
+fn edit_config(
+    f: impl FnOnce(&Path, &str) -> Result<Option<String>, Box<dyn std::error::Error>>,
+) -> Result<bool, Box<dyn std::error::Error>> {
     let path = config_path()?;
-    let raw = std::fs::read_to_string(&path)?;
+    let _lock = fsutil::lock(&path, &locks_dir()?)?;
+    let raw = read_config_locked(&path)?;
+    let Some(updated) = f(&path, &raw)? else {
+        return Ok(false);
+    };
+    if updated != raw {
+        fsutil::backup(&path, &backups_dir()?)?;
+        fsutil::write_atomic(&path, updated.as_bytes())?;
+    }
+    Ok(true)
+}
 
-    let updated = raw
-        .lines()
-        .map(|line| {
-            if line.starts_with("sounds_dir") {
-                format!("sounds_dir = \"{}\"", new_path.replace('"', "\\\""))
-            } else {
-                line.to_string()
+fn set_in(path: &Path, raw: &str, key: &str, value: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let keys = parse_key(key)?;
+
+    // Try the value as TOML first, then as a plain string, then as a
+    // one-element list, so `config set events.stop pack/bye.mp3` does the
+    // obvious thing.
+    let mut candidates: Vec<toml_edit::Value> = value.parse().into_iter().collect();
+    candidates.push(toml_edit::Value::from(value));
+    candidates.push(toml_edit::Value::Array(std::iter::once(value).collect()));
+
+    let mut first_err = None;
+    for candidate in candidates {
+        let mut doc: toml_edit::DocumentMut = raw.parse()?;
+        insert_value(doc.as_table_mut(), &keys, candidate)?;
+        match validate_edit(path, raw, &doc.to_string(), key) {
+            Ok(updated) => return Ok(updated),
+            Err(e) => {
+                first_err.get_or_insert(e);
             }
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
+        }
+    }
+    Err(first_err.unwrap_or_else(|| "no value given".into()))
+}
 
-    let updated = if raw.ends_with('\n') {
-        updated + "\n"
-    } else {
-        updated
-    };
+fn unset_in(path: &Path, raw: &str, key: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let keys = parse_key(key)?;
+    let mut doc: toml_edit::DocumentMut = raw.parse()?;
 
-    std::fs::write(&path, updated)?;
-    Ok(expand_tilde(new_path))
+    let (last, parents) = keys.split_last().ok_or("empty key")?;
+    let mut table: &mut dyn toml_edit::TableLike = doc.as_table_mut();
+    for k in parents {
+        match table.get_mut(k).and_then(|item| item.as_table_like_mut()) {
+            Some(t) => table = t,
+            None => return Ok(None),
+        }
+    }
+    if table.remove(last).is_none() {
+        return Ok(None);
+    }
+    validate_edit(path, raw, &doc.to_string(), key).map(Some)
+}
+
+/// Inserts `value` at the dotted key path, creating intermediate tables and
+/// keeping the formatting of any value it replaces.
+fn insert_value(
+    root: &mut toml_edit::Table,
+    keys: &[String],
+    mut value: toml_edit::Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (last, parents) = keys.split_last().ok_or("empty key")?;
+    let mut table: &mut dyn toml_edit::TableLike = root;
+    for k in parents {
+        let item = table.entry(k).or_insert_with(|| {
+            let mut t = toml_edit::Table::new();
+            t.set_implicit(true);
+            toml_edit::Item::Table(t)
+        });
+        table = item
+            .as_table_like_mut()
+            .ok_or_else(|| format!("'{}' is not a table", k))?;
+    }
+    match table.get_mut(last) {
+        Some(item) => {
+            if let Some(existing) = item.as_value() {
+                *value.decor_mut() = existing.decor().clone();
+            }
+            *item = toml_edit::Item::Value(value);
+        }
+        None => {
+            table.insert(last, toml_edit::Item::Value(value));
+        }
+    }
+    Ok(())
+}
+
+/// Checks an edited config against the schema, rejecting type errors and
+/// keys that would be ignored on load.
+fn validate_edit(path: &Path, before: &str, after: &str, key: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let (_, old_warnings) = parse(path, before).unwrap_or_default();
+    let (_, warnings) = parse(path, after)
+        .map_err(|e| format!("invalid value for '{}': {}", key, e.message))?;
+    if let Some(w) = warnings.into_iter().find(|w| !old_warnings.contains(w)) {
+        return Err(w.into());
+    }
+    Ok(after.to_string())
+}
+
+/// Splits a dotted key into its segments. Segments may be quoted to
+/// include dots, e.g. `events."task.error"`.
+fn parse_key(key: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let keys = toml_edit::Key::parse(key).map_err(|_| format!("invalid key '{}'", key))?;
+    Ok(keys.iter().map(|k| k.get().to_string()).collect())
+}
+
+fn lookup<'a>(table: &'a toml::Table, keys: &[String]) -> Option<&'a toml::Value> {
+    let (first, rest) = keys.split_first()?;
+    let mut value = table.get(first)?;
+    for k in rest {
+        value = value.as_table()?.get(k)?;
+    }
+    Some(value)
 }
 
-/// Replaces the [events] section in the config file with the given map.
+/// Replaces the [events] section in the config file with the given map,
+/// leaving the rest of the file as it was.
 pub fn set_events(events: &HashMap<String, Vec<String>>) -> Result<(), Box<dyn std::error::Error>> {
-    let path = config_path()?;
-    let raw = std::fs::read_to_string(&path)?;
+    edit_config(|path, raw| set_events_in(path, raw, events).map(Some))?;
+    Ok(())
+}
 
-    // Keep everything before [events]
-    let preamble = if let Some(idx) = raw.find("[events]") {
-        raw[..idx].trim_end().to_string() + "\n\n"
-    } else {
-        raw.trim_end().to_string() + "\n\n"
-    };
+/// Replaces the sounds for a single event, removing the event entirely when
+/// `sounds` is empty. Other events and sections are left as they were.
+pub fn set_event(event: &str, sounds: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    edit_config(|path, raw| {
+        edit_events_in(path, raw, |table| {
+            if sounds.is_empty() {
+                table.remove(event);
+            } else {
+                insert_event(table, event, sounds);
+            }
+        })
+        .map(Some)
+    })?;
+    Ok(())
+}
 
+fn set_events_in(
+    path: &Path,
+    raw: &str,
+    events: &HashMap<String, Vec<String>>,
+) -> Result<String, Box<dyn std::error::Error>> {
     // Write events in a consistent order
     // PORT: EVENTS
     // The canonical event order written to config by `hookplayer use <pack>`.
     // Add new event names here to have them recognized and serialized.
     // Note: custom events can also be added directly to config.toml without touching source.
     let order = ["start", "stop", "notify", "permission", "error", "unknown"];
-    let mut section = String::from("[events]\n");
-    for event in &order {
-        if let Some(sounds) = events.get(*event) {
-            if !sounds.is_empty() {
-                let quoted: Vec<String> = sounds.iter().map(|s| format!("\"{}\"", s)).collect();
-                section.push_str(&format!("{:<10} = [{}]\n", event, quoted.join(", ")));
+    edit_events_in(path, raw, |table| {
+        table.clear();
+        for event in &order {
+            if let Some(sounds) = events.get(*event)
+                && !sounds.is_empty()
+            {
+                insert_event(table, event, sounds);
             }
         }
+    })
+}
+
+/// Removes every event sound for which `matches` is true, in [events] and
+/// in each profile's events. `[sounds]` entries whose file matches are
+/// removed too, along with the `@name` references to them. Events left
+/// without sounds are dropped so they fall back as if never configured.
+/// Returns the removed `(event, sound)` pairs, with `sounds` standing in for
+/// the event of a library entry; with `dry_run` the file is left alone.
+pub fn strip_sounds(
+    matches: impl Fn(&str) -> bool,
+    dry_run: bool,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    if !config_path()?.exists() {
+        return Ok(Vec::new());
     }
+    let mut removed = Vec::new();
+    edit_config(|path, raw| {
+        let updated = strip_sounds_in(path, raw, &matches, &mut removed)?;
+        Ok((!dry_run && !removed.is_empty()).then_some(updated))
+    })?;
+    Ok(removed)
+}
 
-    std::fs::write(&path, format!("{}{}", preamble, section))?;
-    Ok(())
+fn strip_sounds_in(
+    path: &Path,
+    raw: &str,
+    matches: &dyn Fn(&str) -> bool,
+    removed: &mut Vec<(String, String)>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    fn strip(
+        events: Option<&mut toml_edit::Item>,
+        prefix: &str,
+        matches: &dyn Fn(&str) -> bool,
+        removed: &mut Vec<(String, String)>,
+    ) {
+        let Some(table) = events.and_then(|e| e.as_table_like_mut()) else {
+            return;
+        };
+        let mut emptied = Vec::new();
+        for (event, item) in table.iter_mut() {
+            let Some(list) = item.as_array_mut() else {
+                continue;
+            };
+            // Keep the list's layout: whatever now comes first takes over
+            // the old first entry's leading whitespace.
+            let lead = list.get(0).and_then(|v| v.decor().prefix()).cloned();
+            list.retain(|v| match v.as_str() {
+                Some(sound) if matches(sound) => {
+                    removed.push((format!("{}{}", prefix, event), sound.to_string()));
+                    false
+                }
+                _ => true,
+            });
+            if let (Some(first), Some(lead)) = (list.get_mut(0), lead) {
+                first.decor_mut().set_prefix(lead);
+            }
+            if list.is_empty() {
+                emptied.push(event.get().to_string());
+            }
+        }
+        for event in emptied {
+            table.remove(&event);
+        }
+    }
+
+    let library: HashMap<String, SoundDef> = match toml::from_str::<toml::Table>(raw)?.remove("sounds") {
+        Some(sounds) => sounds.try_into()?,
+        None => HashMap::new(),
+    };
+    let mut aliases: Vec<&String> = library.iter().filter(|(_, def)| matches(&def.file)).map(|(name, _)| name).collect();
+    aliases.sort();
+    // An `@name` reference, or an exclusion of one, goes with its alias.
+    let refers = |sound: &str| match sound.trim_start_matches('!').strip_prefix('@') {
+        Some(alias) => aliases.iter().any(|a| *a == alias),
+        None => matches(sound),
+    };
+
+    let mut doc: toml_edit::DocumentMut = raw.parse()?;
+    strip(doc.get_mut("events"), "", &refers, removed);
+    if let Some(profiles) = doc.get_mut("profiles").and_then(|p| p.as_table_like_mut()) {
+        for (name, profile) in profiles.iter_mut() {
+            let prefix = format!("profiles.{}.", name.get());
+            strip(profile.get_mut("events"), &prefix, &refers, removed);
+        }
+    }
+    if let Some(sounds) = doc.get_mut("sounds").and_then(|s| s.as_table_like_mut()) {
+        for alias in aliases {
+            sounds.remove(alias);
+            removed.push(("sounds".to_string(), format!("@{}", alias)));
+        }
+    }
+    validate_edit(path, raw, &doc.to_string(), "events")
+}
+
+/// Applies `f` to the [events] table of the config source, creating the
+/// table if needed, and returns the validated result.
+fn edit_events_in(
+    path: &Path,
+    raw: &str,
+    f: impl FnOnce(&mut toml_edit::Table),
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut doc: toml_edit::DocumentMut = raw.parse()?;
+    let events = doc
+        .entry("events")
+        .or_insert_with(toml_edit::table)
+        .as_table_mut()
+        .ok_or("'events' is not a table")?;
+    f(events);
+    validate_edit(path, raw, &doc.to_string(), "events")
 }
 
+/// Writes `event = [...]`, replacing an existing list in place or padding a
+/// new key so event lists line up.
+fn insert_event(table: &mut toml_edit::Table, event: &str, sounds: &[String]) {
+    let mut list: toml_edit::Value = sounds.iter().map(String::as_str).collect::<toml_edit::Array>().into();
+    if let Some(existing) = table.get_mut(event) {
+        if let Some(old) = existing.as_value() {
+            *list.decor_mut() = old.decor().clone();
+        }
+        *existing = toml_edit::Item::Value(list);
+        return;
+    }
+    let width = toml_edit::Key::new(event).display_repr().len();
+    let mut key = toml_edit::Key::new(event);
+    *key.leaf_decor_mut() = toml_edit::Decor::new("", " ".repeat(10usize.saturating_sub(width) + 1));
+    table.insert_formatted(&key, toml_edit::Item::Value(list));
+}
+
+/// Directory for files hookplayer downloads and can fetch again, such as
+/// sounds referenced by URL.
+pub fn cache_dir() -> PathBuf {
+    match std::env::var("XDG_CACHE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("hookplayer"),
+        _ => expand_tilde("~/.cache/hookplayer"),
+    }
+}
+
+#[allow(clippy::collapsible_if)]
 pub fn expand_tilde(path: &str) -> PathBuf {
     if let Some(stripped) = path.strip_prefix("~/") {
         if let Ok(home) = std::env::var("HOME") {
@@ -108,7 +1104,23 @@ pub fn expand_tilde(path: &str) -> PathBuf {
     PathBuf::from(path)
 }
 
-fn config_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+/// Where local registry overlay files (`*.toml`, `*.json`) live.
+pub fn registry_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    Ok(config_path()?.with_file_name("registry.d"))
+}
+
+/// Where timestamped backups of config.toml and Claude's settings.json go.
+pub fn backups_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    Ok(config_path()?.with_file_name("backups"))
+}
+
+/// Lock files guarding read-modify-write cycles on files hookplayer edits,
+/// including ones it doesn't own like Claude's settings.json.
+pub fn locks_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    Ok(config_path()?.with_file_name("locks"))
+}
+
+pub fn config_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
     let home = std::env::var("HOME").map_err(|_| "HOME not set")?;
     Ok(PathBuf::from(home).join(".config/hookplayer/config.toml"))
 }
@@ -123,9 +1135,22 @@ mod tests {
             sounds_dir: sounds_dir.to_string(),
             volume: 0.5,
             events,
+            ..Default::default()
         }
     }
 
+    fn paths(clips: Vec<sounds::Clip>) -> Vec<PathBuf> {
+        clips.into_iter().map(|c| c.path).collect()
+    }
+
+    fn with_fallbacks(mut cfg: Config, fallbacks: &[(&str, &[&str])]) -> Config {
+        for (event, to) in fallbacks {
+            cfg.fallbacks
+                .insert(event.to_string(), to.iter().map(|s| s.to_string()).collect());
+        }
+        cfg
+    }
+
     // --- expand_tilde ---
 
     #[test]
@@ -150,23 +1175,73 @@ mod tests {
     // --- sounds_dir_abs ---
 
     #[test]
-    fn sounds_dir_abs_uses_env_var_override() {
-        // Use a unique env var key to avoid parallel test conflicts
-        unsafe { std::env::set_var("HOOKPLAYER_SOUNDS_DIR", "/override/sounds") };
-        let cfg = make_config("~/default/sounds", HashMap::new());
-        let result = cfg.sounds_dir_abs();
-        unsafe { std::env::remove_var("HOOKPLAYER_SOUNDS_DIR") };
-        assert_eq!(result, PathBuf::from("/override/sounds"));
-    }
-
-    #[test]
-    fn sounds_dir_abs_falls_back_to_config() {
-        unsafe { std::env::remove_var("HOOKPLAYER_SOUNDS_DIR") };
+    fn sounds_dir_abs_expands_config_value() {
         let home = std::env::var("HOME").unwrap();
         let cfg = make_config("~/mysounds", HashMap::new());
         assert_eq!(cfg.sounds_dir_abs(), PathBuf::from(&home).join("mysounds"));
     }
 
+    // --- migrate ---
+
+    #[test]
+    fn migrate_leaves_current_files_alone() {
+        let raw = "version = 1\n[events]\nstop = [\"a.mp3\"]\n";
+        assert!(migrate(Path::new("config.toml"), raw, MIGRATIONS).unwrap().is_none());
+        let bare = "[events]\nstop = [\"a.mp3\"]\n";
+        assert!(migrate(Path::new("config.toml"), bare, MIGRATIONS).unwrap().is_none());
+    }
+
+    #[test]
+    fn migrate_rejects_newer_versions() {
+        let err = migrate(Path::new("config.toml"), "version = 9\n", MIGRATIONS).err().unwrap();
+        assert!(err.message.contains("newer than this hookplayer supports"), "{}", err);
+    }
+
+    /// A stand-in step: pretend version 1 called `mute` `muted`.
+    fn rename_mute(doc: &mut toml_edit::DocumentMut) {
+        if let Some(mute) = doc.remove("muted") {
+            doc.insert("mute", mute);
+        }
+    }
+
+    #[test]
+    fn upgrade_file_bumps_version_and_backs_up_the_original() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        let backups = dir.path().join("backups");
+        let raw = "# my sounds\nvolume   = 0.5  # quiet\nmuted = true\n\n[events]\nstop = [\"a.mp3\"]\n";
+        std::fs::write(&path, raw).unwrap();
+
+        // This build's parse would reject version 2, so only the TOML is checked.
+        let steps: &[Step] = &[rename_mute];
+        let check = |output: &str| parse_table(&path, output).map(|_| ());
+        let out = upgrade_file(&path, steps, &backups, check).unwrap();
+        assert_eq!(
+            out,
+            "# my sounds\nvolume   = 0.5  # quiet\nmute = true\nversion = 2\n\n[events]\nstop = [\"a.mp3\"]\n"
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), out);
+
+        let saved = fsutil::backups("config.toml", &backups);
+        assert_eq!(saved.len(), 1);
+        assert_eq!(std::fs::read_to_string(&saved[0]).unwrap(), raw);
+
+        // Already current: nothing is written or backed up again.
+        assert_eq!(upgrade_file(&path, steps, &backups, check).unwrap(), out);
+        assert_eq!(fsutil::backups("config.toml", &backups).len(), 1);
+    }
+
+    #[test]
+    fn diff_shows_changed_lines_with_context() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "a\nb\nc\nd\nE\nf\ng\nh\n";
+        assert_eq!(
+            diff("old", old, "new", new),
+            "--- old\n+++ new\n@@ -2,7 +2,7 @@\n b\n c\n d\n-e\n+E\n f\n g\n h\n"
+        );
+        assert_eq!(diff("old", old, "new", old), "--- old\n+++ new\n");
+    }
+
     // --- sounds_for_event ---
 
     #[test]
@@ -175,7 +1250,7 @@ mod tests {
         events.insert("start".to_string(), vec!["pack/hello.mp3".to_string()]);
         let cfg = make_config("/sounds", events);
 
-        let paths = cfg.sounds_for_event("start");
+        let paths = paths(cfg.sounds_for_event("start"));
         assert_eq!(paths, vec![PathBuf::from("/sounds/pack/hello.mp3")]);
     }
 
@@ -185,17 +1260,359 @@ mod tests {
         events.insert("unknown".to_string(), vec!["pack/default.mp3".to_string()]);
         let cfg = make_config("/sounds", events);
 
-        let paths = cfg.sounds_for_event("unrecognized_event");
+        let paths = paths(cfg.sounds_for_event("unrecognized_event"));
         assert_eq!(paths, vec![PathBuf::from("/sounds/pack/default.mp3")]);
     }
 
     #[test]
     fn sounds_for_event_returns_empty_when_no_match_and_no_unknown() {
         let cfg = make_config("/sounds", HashMap::new());
-        let paths = cfg.sounds_for_event("start");
+        let paths = paths(cfg.sounds_for_event("start"));
         assert!(paths.is_empty());
     }
 
+    // --- fallback_chain ---
+
+    #[test]
+    fn fallback_chain_walks_hierarchy_then_unknown() {
+        let cfg = make_config("/sounds", HashMap::new());
+        assert_eq!(
+            cfg.fallback_chain("task.error.build"),
+            vec!["task.error.build", "task.error", "task", "unknown"]
+        );
+    }
+
+    #[test]
+    fn fallback_chain_tries_explicit_fallbacks_before_parent() {
+        let cfg = with_fallbacks(
+            make_config("/sounds", HashMap::new()),
+            &[("task.error", &["error"]), ("permission", &["notify"])],
+        );
+        assert_eq!(
+            cfg.fallback_chain("task.error.build"),
+            vec!["task.error.build", "task.error", "error", "task", "unknown"]
+        );
+        assert_eq!(cfg.fallback_chain("permission"), vec!["permission", "notify", "unknown"]);
+    }
+
+    #[test]
+    fn sounds_for_event_uses_explicit_fallback() {
+        let mut events = HashMap::new();
+        events.insert("notify".to_string(), vec!["pack/ping.mp3".to_string()]);
+        events.insert("unknown".to_string(), vec!["pack/default.mp3".to_string()]);
+        let cfg = with_fallbacks(make_config("/sounds", events), &[("permission", &["notify"])]);
+
+        assert_eq!(paths(cfg.sounds_for_event("permission")), vec![PathBuf::from("/sounds/pack/ping.mp3")]);
+    }
+
+    #[test]
+    fn sounds_for_event_uses_parent_event() {
+        let mut events = HashMap::new();
+        events.insert("task.error".to_string(), vec!["pack/oops.mp3".to_string()]);
+        let cfg = make_config("/sounds", events);
+
+        assert_eq!(paths(cfg.sounds_for_event("task.error.build")), vec![PathBuf::from("/sounds/pack/oops.mp3")]);
+        assert!(paths(cfg.sounds_for_event("task")).is_empty());
+    }
+
+    #[test]
+    fn parse_rejects_fallback_cycle() {
+        let raw = "[fallbacks]\npermission = [\"notify\"]\nnotify = [\"permission\"]\n";
+        let err = parse(Path::new("config.toml"), raw).unwrap_err();
+        assert_eq!(err.message, "fallback cycle: notify -> permission -> notify");
+    }
+
+    #[test]
+    fn parse_rejects_cycle_through_parent() {
+        let raw = "[fallbacks]\ntask = [\"task.error\"]\n";
+        let err = parse(Path::new("config.toml"), raw).unwrap_err();
+        assert_eq!(err.message, "fallback cycle: task -> task.error -> task");
+    }
+
+    // --- sound library ---
+
+    #[test]
+    fn parse_accepts_short_and_detailed_sounds() {
+        let raw = "[sounds]\nding = \"pack/ping.wav\"\nboom = { file = \"pack/boom.mp3\", gain = 0.8, end = 1.5 }\n";
+        let (cfg, _) = parse(Path::new("config.toml"), raw).unwrap();
+        assert_eq!(
+            cfg.sounds["ding"],
+            SoundDef { file: "pack/ping.wav".into(), gain: 1.0, start: None, end: None }
+        );
+        assert_eq!(cfg.sounds["boom"].gain, 0.8);
+        assert_eq!(cfg.sounds["boom"].end, Some(1.5));
+    }
+
+    #[test]
+    fn clips_for_event_resolves_aliases() {
+        let raw = "sounds_dir = \"/sounds\"\n[sounds]\nding = { file = \"pack/ping.wav\", gain = 0.5 }\n[events]\nnotify = [\"@ding\", \"pack/other.mp3\"]\n";
+        let (cfg, _) = parse(Path::new("config.toml"), raw).unwrap();
+        let clips = cfg.sounds_for_event("notify");
+        assert_eq!(clips.len(), 2);
+        assert_eq!(clips[0].path, PathBuf::from("/sounds/pack/ping.wav"));
+        assert_eq!(clips[0].gain, 0.5);
+        assert_eq!(clips[1].gain, 1.0);
+    }
+
+    #[test]
+    fn parse_rejects_dangling_alias() {
+        let raw = "[sounds]\nding = \"a.wav\"\n[events]\nnotify = [\"@dnig\"]\n";
+        let err = parse(Path::new("config.toml"), raw).unwrap_err();
+        assert_eq!(err.message, "events.notify: unknown sound '@dnig' (did you mean '@ding'?)");
+    }
+
+    #[test]
+    fn parse_rejects_unsupported_entries() {
+        let raw = "[events]\nnotify = [\"ftp://example.com/a.mp3\"]\n";
+        let err = parse(Path::new("config.toml"), raw).unwrap_err();
+        assert!(err.message.starts_with("events.notify: 'ftp://"), "{}", err.message);
+    }
+
+    #[test]
+    fn parse_rejects_inverted_trim() {
+        let raw = "[sounds]\nding = { file = \"a.wav\", start = 2.0, end = 1.0 }\n";
+        let err = parse(Path::new("config.toml"), raw).unwrap_err();
+        assert!(err.message.starts_with("sounds.ding: start"), "{}", err.message);
+    }
+
+    #[test]
+    fn parse_rejects_negative_or_nan_trim() {
+        let raw = "[sounds]\nding = { file = \"a.wav\", start = -1.0 }\n";
+        let err = parse(Path::new("config.toml"), raw).unwrap_err();
+        assert_eq!(err.message, "sounds.ding: start must be a non-negative number of seconds");
+        let raw = "[sounds]\nding = { file = \"a.wav\", end = nan }\n";
+        let err = parse(Path::new("config.toml"), raw).unwrap_err();
+        assert_eq!(err.message, "sounds.ding: end must be a non-negative number of seconds");
+    }
+
+    // --- profiles ---
+
+    #[test]
+    fn apply_profile_scales_volume_and_overrides_events() {
+        let raw = "volume = 0.8\n[events]\nstart = [\"a.mp3\"]\nstop = [\"b.mp3\"]\n\n[profiles.night]\nvolume = 0.5\n[profiles.night.events]\nstop = [\"soft.mp3\"]\n";
+        let (mut cfg, warnings) = parse(Path::new("config.toml"), raw).unwrap();
+        assert!(warnings.is_empty());
+        cfg.apply_profile("night").unwrap();
+        assert_eq!(cfg.gain("stop").level(1.0), 0.4);
+        assert_eq!(cfg.events["start"], vec!["a.mp3".to_string()]);
+        assert_eq!(cfg.events["stop"], vec!["soft.mp3".to_string()]);
+    }
+
+    #[test]
+    fn volumes_accept_percent_and_db() {
+        let raw = "volume = \"50%\"\n[event_volume]\nnotify = \"-6dB\"\n[sounds]\nding = { file = \"a.wav\", gain = \"200%\" }\n";
+        let (cfg, _) = parse(Path::new("config.toml"), raw).unwrap();
+        assert_eq!(cfg.volume, 0.5);
+        assert!((cfg.event_volume["notify"] - 0.501).abs() < 0.001);
+        assert_eq!(cfg.sounds["ding"].gain, 2.0);
+
+        let err = parse(Path::new("config.toml"), "volume = \"loud\"\n").unwrap_err();
+        assert!(err.message.contains("invalid volume 'loud'"), "{}", err);
+        let err = parse(Path::new("config.toml"), "volume = \"150%\"\n").unwrap_err();
+        assert!(err.message.contains("volume: 1.5 is out of range"), "{}", err);
+    }
+
+    #[test]
+    fn event_volume_applies_to_dotted_children() {
+        let mut cfg = make_config("/sounds", HashMap::new());
+        cfg.event_volume.insert("task".into(), 0.5);
+        cfg.event_volume.insert("task.error".into(), 1.0);
+        assert_eq!(cfg.gain("task.done").level(1.0), 0.25);
+        assert_eq!(cfg.gain("task.error.build").level(1.0), 0.5);
+        assert_eq!(cfg.gain("stop").level(1.0), 0.5);
+    }
+
+    #[test]
+    fn registry_sources_put_default_first() {
+        let raw = "[[registries]]\nname = \"team\"\nurl = \"https://example.com/index.json\"\n";
+        let (cfg, _) = parse(Path::new("config.toml"), raw).unwrap();
+        let names: Vec<String> = cfg.registry_sources().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["default", "team"]);
+
+        let dup = format!("{}{}", raw, raw);
+        assert!(parse(Path::new("config.toml"), &dup).is_err());
+        let reserved = raw.replace("team", "default");
+        assert!(parse(Path::new("config.toml"), &reserved).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_unknown_profile() {
+        let raw = "profile = \"nigth\"\n[profiles.night]\nvolume = 0.2\n";
+        let err = parse(Path::new("config.toml"), raw).unwrap_err();
+        assert_eq!(err.message, "profile: unknown profile 'nigth' (did you mean 'night'?)");
+    }
+
+    #[test]
+    fn parse_rejects_bad_schedule_window() {
+        let raw = "[schedule.late]\nfrom = \"25:00\"\nto = \"07:00\"\nmute = true\n";
+        let err = parse(Path::new("config.toml"), raw).unwrap_err();
+        assert!(err.message.starts_with("schedule.late: "), "{}", err.message);
+    }
+
+    // --- set / unset ---
+
+    const SAMPLE: &str = "# my config\nsounds_dir = \"~/s\"  # where sounds live\nvolume = 0.5\n\n[events]\nstart = [\"a.mp3\"]\n";
+
+    fn set_sample(key: &str, value: &str) -> Result<String, Box<dyn std::error::Error>> {
+        set_in(Path::new("config.toml"), SAMPLE, key, value)
+    }
+
+    #[test]
+    fn set_replaces_value_and_keeps_comments() {
+        let out = set_sample("sounds_dir", "~/other").unwrap();
+        assert!(out.starts_with("# my config\nsounds_dir = \"~/other\"  # where sounds live\n"), "{}", out);
+        assert!(out.contains("start = [\"a.mp3\"]"));
+    }
+
+    #[test]
+    fn set_parses_numbers() {
+        let out = set_sample("volume", "0.8").unwrap();
+        assert!(out.contains("volume = 0.8\n"), "{}", out);
+    }
+
+    #[test]
+    fn set_wraps_single_sound_in_a_list() {
+        let out = set_sample("events.stop", "pack/bye.mp3").unwrap();
+        let (cfg, _) = parse(Path::new("config.toml"), &out).unwrap();
+        assert_eq!(cfg.events["stop"], vec!["pack/bye.mp3".to_string()]);
+    }
+
+    #[test]
+    fn set_rejects_wrong_type() {
+        let err = set_sample("volume", "loud").unwrap_err();
+        assert!(err.to_string().contains("invalid value for 'volume'"), "{}", err);
+    }
+
+    #[test]
+    fn set_rejects_unknown_key_with_suggestion() {
+        let err = set_sample("volum", "0.3").unwrap_err();
+        assert!(err.to_string().contains("did you mean 'volume'"), "{}", err);
+    }
+
+    #[test]
+    fn unset_removes_key() {
+        let out = unset_in(Path::new("config.toml"), SAMPLE, "events.start").unwrap().unwrap();
+        let (cfg, _) = parse(Path::new("config.toml"), &out).unwrap();
+        assert!(cfg.events.is_empty());
+        assert!(out.contains("# my config"));
+    }
+
+    #[test]
+    fn unset_missing_key_is_noop() {
+        let out = unset_in(Path::new("config.toml"), SAMPLE, "events.stop").unwrap();
+        assert!(out.is_none());
+    }
+
+    // --- set_events / edit_events_in ---
+
+    #[test]
+    fn set_events_keeps_sections_after_events() {
+        let raw = "volume = 0.5\n\n# events below\n[events]\nstart = [\"old.mp3\"]\n\n[extra]\nkeep = 1\n";
+        let mut events = HashMap::new();
+        events.insert("stop".to_string(), vec!["pack/bye.mp3".to_string()]);
+        let out = set_events_in(Path::new("config.toml"), raw, &events).unwrap();
+        assert_eq!(
+            out,
+            "volume = 0.5\n\n# events below\n[events]\nstop       = [\"pack/bye.mp3\"]\n\n[extra]\nkeep = 1\n"
+        );
+    }
+
+    #[test]
+    fn edit_events_preserves_other_events() {
+        let out = edit_events_in(Path::new("config.toml"), SAMPLE, |t| {
+            insert_event(t, "stop", &["b.mp3".to_string()])
+        })
+        .unwrap();
+        assert!(out.ends_with("start = [\"a.mp3\"]\nstop       = [\"b.mp3\"]\n"), "{}", out);
+    }
+
+    // --- strip_sounds ---
+
+    #[test]
+    fn strip_sounds_removes_entries_and_empty_events() {
+        let raw = "[events]\nstop = [\"peon/a.wav\", \"mine/b.wav\"]\nerror = [\"peon/c.wav\"]\n\n\
+                   [profiles.work.events]\nstart = [\"peon/d.wav\", \"other/e.wav\"]\n";
+        let mut removed = Vec::new();
+        let out = strip_sounds_in(Path::new("c.toml"), raw, &|s| s.starts_with("peon/"), &mut removed).unwrap();
+        assert_eq!(
+            out,
+            "[events]\nstop = [\"mine/b.wav\"]\n\n[profiles.work.events]\nstart = [\"other/e.wav\"]\n"
+        );
+        assert_eq!(
+            removed,
+            vec![
+                ("stop".to_string(), "peon/a.wav".to_string()),
+                ("error".to_string(), "peon/c.wav".to_string()),
+                ("profiles.work.start".to_string(), "peon/d.wav".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn strip_sounds_removes_aliases_into_the_pack() {
+        let raw = "[events]\nstop = [\"@bye\", \"@keep\"]\nstart = [\"mine/*\", \"!@bye\"]\n\n\
+                   [sounds]\nbye = { file = \"peon/bye.wav\", gain = 0.5 }\nkeep = \"mine/keep.wav\"\n";
+        let mut removed = Vec::new();
+        let out = strip_sounds_in(Path::new("c.toml"), raw, &|s| s.starts_with("peon/"), &mut removed).unwrap();
+        assert_eq!(
+            out,
+            "[events]\nstop = [\"@keep\"]\nstart = [\"mine/*\"]\n\n[sounds]\nkeep = \"mine/keep.wav\"\n"
+        );
+        assert_eq!(
+            removed,
+            vec![
+                ("stop".to_string(), "@bye".to_string()),
+                ("start".to_string(), "!@bye".to_string()),
+                ("sounds".to_string(), "@bye".to_string()),
+            ]
+        );
+    }
+
+    // --- parse ---
+
+    #[test]
+    fn parse_fills_in_missing_fields() {
+        let (cfg, warnings) = parse(Path::new("config.toml"), "[events]\n").unwrap();
+        assert_eq!(cfg.sounds_dir, DEFAULT_SOUNDS_DIR);
+        assert_eq!(cfg.volume, DEFAULT_VOLUME);
+        assert!(cfg.events.is_empty());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn parse_accepts_empty_file() {
+        let (cfg, _) = parse(Path::new("config.toml"), "").unwrap();
+        assert_eq!(cfg.volume, DEFAULT_VOLUME);
+    }
+
+    #[test]
+    fn parse_error_reports_line_column_and_snippet() {
+        let raw = "sounds_dir = \"~/s\"\nvolume = \"loud\"\n";
+        let err = parse(Path::new("/tmp/config.toml"), raw).unwrap_err();
+        assert_eq!(err.location, Some((2, 10)));
+        let shown = err.to_string();
+        assert!(shown.starts_with("/tmp/config.toml:2:10: "), "{}", shown);
+        assert!(shown.contains("2 | volume = \"loud\""), "{}", shown);
+    }
+
+    #[test]
+    fn parse_error_on_syntax() {
+        let err = parse(Path::new("config.toml"), "volume = \n").unwrap_err();
+        assert_eq!(err.location.map(|(line, _)| line), Some(1));
+    }
+
+    #[test]
+    fn parse_warns_about_misspelled_keys() {
+        let (_, warnings) = parse(Path::new("config.toml"), "volum = 0.3\ncolour = 1\n").unwrap();
+        assert_eq!(
+            warnings,
+            vec![
+                "unknown key 'colour'".to_string(),
+                "unknown key 'volum' (did you mean 'volume'?)".to_string(),
+            ]
+        );
+    }
+
     #[test]
     fn sounds_for_event_returns_multiple_sounds() {
         let mut events = HashMap::new();
@@ -205,7 +1622,7 @@ mod tests {
         );
         let cfg = make_config("/sounds", events);
 
-        let paths = cfg.sounds_for_event("notify");
+        let paths = paths(cfg.sounds_for_event("notify"));
         assert_eq!(paths.len(), 2);
     }
 }
@@ -0,0 +1,172 @@
+use crate::config::{self, Config};
+use std::path::Path;
+
+/// Prints every configured event with its sound count and any missing files.
+pub fn list(cfg: &Config) {
+    let mut events: Vec<&String> = cfg.events.keys().collect();
+    events.sort();
+
+    for event in &events {
+        let entries = &cfg.events[*event];
+        let count = cfg.expand(entries).len();
+        let missing: Vec<&String> = entries.iter().filter(|s| is_missing(cfg, s)).collect();
+        if missing.is_empty() {
+            println!("  {:<12} {} sound(s)", event, count);
+        } else {
+            println!("  {:<12} {} sound(s), {} missing", event, count, missing.len());
+            for m in missing {
+                println!("      missing: {}", m);
+            }
+        }
+    }
+    println!("\n{} event(s) configured", events.len());
+}
+
+/// Whether an entry plays nothing: a file that doesn't exist, or a glob,
+/// directory or `@name` that matches no files. Exclusions and URLs never
+/// count as missing.
+fn is_missing(cfg: &Config, entry: &str) -> bool {
+    if entry.starts_with('!') {
+        return false;
+    }
+    let clips = cfg.expand(&[entry.to_string()]);
+    clips.iter().all(|c| c.url.is_none() && !c.path.is_file())
+}
+
+/// Prints the fallback chain for an event and which step would play.
+pub fn resolve(cfg: &Config, event: &str) {
+    let chosen = cfg.resolve_event(event);
+    for step in cfg.fallback_chain(event) {
+        let marker = if chosen.as_deref() == Some(step.as_str()) { "->" } else { "  " };
+        match cfg.events.get(&step) {
+            Some(entries) => {
+                let count = cfg.expand(entries).len();
+                println!("{} {:<24} {} sound(s)", marker, step, count)
+            }
+            None => println!("{} {:<24} not configured", marker, step),
+        }
+    }
+    if chosen.is_none() {
+        println!("\nNo sounds configured for '{}'; it will be silent.", event);
+    }
+}
+
+/// Appends sounds to an event, skipping ones it already has.
+/// Returns the sounds that were added.
+///
+/// Like the other editing commands, this works on the list in config.toml,
+/// so sounds from includes or a project file are never copied into it.
+pub fn add(cfg: &Config, event: &str, sounds: &[String]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let base = cfg.sounds_dir_abs();
+    let mut list = config::get_event(event)?.unwrap_or_default();
+    let mut added = Vec::new();
+
+    for sound in sounds {
+        let entry = relative_to(sound, &base);
+        if is_missing(cfg, &entry) {
+            eprintln!("Warning: {} matches no sound files in {}", entry, base.display());
+        }
+        if !list.contains(&entry) {
+            list.push(entry.clone());
+            added.push(entry);
+        }
+    }
+
+    config::set_event(event, &list)?;
+    Ok(added)
+}
+
+/// Removes one sound from an event, or the whole event when `sound` is None.
+/// Returns the sounds that were removed.
+pub fn remove(cfg: &Config, event: &str, sound: Option<&str>) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let list = saved_event(event)?;
+
+    let (removed, kept): (Vec<String>, Vec<String>) = match sound {
+        Some(s) => {
+            let entry = relative_to(s, &cfg.sounds_dir_abs());
+            list.into_iter().partition(|e| *e == entry)
+        }
+        None => (list, Vec::new()),
+    };
+    if removed.is_empty() {
+        return Err(format!("event '{}' has no sound '{}'", event, sound.unwrap_or_default()).into());
+    }
+
+    config::set_event(event, &kept)?;
+    Ok(removed)
+}
+
+/// Copies the sound list of one event to another, replacing its sounds.
+pub fn copy(from: &str, to: &str) -> Result<usize, Box<dyn std::error::Error>> {
+    let list = saved_event(from)?;
+    config::set_event(to, &list)?;
+    Ok(list.len())
+}
+
+fn saved_event(event: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    config::get_event(event)?.ok_or_else(|| format!("event '{}' is not configured in config.toml", event).into())
+}
+
+/// Turns a sound argument into a config entry. Paths that exist on disk
+/// (as completed by the shell) are made relative to the sounds dir when
+/// they live inside it; anything else is taken as already relative.
+fn relative_to(sound: &str, base: &Path) -> String {
+    let path = Path::new(sound);
+    if let (Ok(abs), Ok(base)) = (path.canonicalize(), base.canonicalize()) {
+        return match abs.strip_prefix(&base) {
+            Ok(rel) => rel.to_string_lossy().into_owned(),
+            Err(_) => abs.to_string_lossy().into_owned(),
+        };
+    }
+    sound.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn relative_to_strips_sounds_dir() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("pack")).unwrap();
+        fs::write(dir.path().join("pack/a.mp3"), "").unwrap();
+
+        let arg = dir.path().join("pack/a.mp3").display().to_string();
+        assert_eq!(relative_to(&arg, dir.path()), "pack/a.mp3");
+    }
+
+    #[test]
+    fn is_missing_resolves_entries_like_playback() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("pack")).unwrap();
+        fs::write(dir.path().join("pack/a.mp3"), "").unwrap();
+        let mut cfg = Config { sounds_dir: dir.path().display().to_string(), ..Default::default() };
+        cfg.sounds.insert("ding".into(), config::SoundDef { file: "pack/a.mp3".into(), gain: 1.0, start: None, end: None });
+
+        for entry in ["pack/a.mp3", "pack/*.mp3", "pack/", "@ding", "https://example.com/x.wav", "!pack/b.mp3"] {
+            assert!(!is_missing(&cfg, entry), "{}", entry);
+        }
+        for entry in ["pack/b.mp3", "pack/*.wav", "other/", "@nope"] {
+            assert!(is_missing(&cfg, entry), "{}", entry);
+        }
+    }
+
+    #[test]
+    fn relative_to_keeps_entries_that_are_not_paths() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(relative_to("pack/missing.mp3", dir.path()), "pack/missing.mp3");
+    }
+
+    #[test]
+    fn relative_to_keeps_files_outside_sounds_dir_absolute() {
+        let sounds = TempDir::new().unwrap();
+        let other = TempDir::new().unwrap();
+        fs::write(other.path().join("x.wav"), "").unwrap();
+
+        let arg = other.path().join("x.wav").display().to_string();
+        let expected = other.path().canonicalize().unwrap().join("x.wav");
+        assert_eq!(relative_to(&arg, sounds.path()), expected.display().to_string());
+    }
+}
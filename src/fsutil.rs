@@ -0,0 +1,177 @@
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How many backups to keep per file; older ones are pruned.
+const KEEP_BACKUPS: usize = 20;
+
+/// Replaces `path` with `contents` without ever leaving a half-written file:
+/// the data goes to a temp file in the same directory, is synced, and is
+/// then renamed over the original. The original's permissions are kept.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let tmp = dir.join(format!(
+        ".{}.{}-{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| {
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents)?;
+        if let Ok(meta) = fs::metadata(path) {
+            file.set_permissions(meta.permissions())?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Takes an exclusive advisory lock for read-modify-write cycles on `path`,
+/// blocking until any other hookplayer process holding it is done. The lock
+/// file lives in `dir`, named by a hash of the absolute path, so nothing is
+/// left next to files hookplayer doesn't own. It is released when the
+/// returned handle drops.
+pub fn lock(path: &Path, dir: &Path) -> io::Result<File> {
+    let digest = Sha256::digest(std::path::absolute(path)?.as_os_str().as_encoded_bytes());
+    let hash: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    let lock_path = dir.join(format!("{}-{}.lock", path.file_name().unwrap_or_default().to_string_lossy(), hash));
+    fs::create_dir_all(dir)?;
+    let file = File::options().create(true).truncate(false).write(true).open(lock_path)?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Copies `path` into `dir` as `<name>.<timestamp>.bak` and prunes old
+/// backups of the same file. Returns None if `path` doesn't exist.
+pub fn backup(path: &Path, dir: &Path) -> io::Result<Option<PathBuf>> {
+    let Ok(contents) = fs::read(path) else {
+        return Ok(None);
+    };
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let stamp = chrono::Local::now().format("%Y%m%dT%H%M%S%.3f");
+    let dest = dir.join(format!("{}.{}.bak", name, stamp));
+    write_atomic(&dest, &contents)?;
+
+    let all = backups(&name, dir);
+    for old in &all[..all.len().saturating_sub(KEEP_BACKUPS)] {
+        let _ = fs::remove_file(old);
+    }
+    Ok(Some(dest))
+}
+
+/// Puts `backup` back in place of `target`, under the target's lock in
+/// `locks`. The current target is itself backed up first, so a restore can
+/// be undone; returns that backup's path.
+pub fn restore(backup_file: &Path, target: &Path, dir: &Path, locks: &Path) -> io::Result<Option<PathBuf>> {
+    let contents = fs::read(backup_file)?;
+    let _lock = lock(target, locks)?;
+    let saved = backup(target, dir)?;
+    write_atomic(target, &contents)?;
+    Ok(saved)
+}
+
+/// Backups of the file called `name` in `dir`, oldest first.
+pub fn backups(name: &str, dir: &Path) -> Vec<PathBuf> {
+    let prefix = format!("{}.", name);
+    let mut found: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            let file = p.file_name().unwrap_or_default().to_string_lossy();
+            file.strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".bak"))
+                .is_some_and(|stamp| stamp.starts_with(|c: char| c.is_ascii_digit()))
+        })
+        .collect();
+    // Timestamps sort lexically.
+    found.sort();
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn write_atomic_replaces_and_leaves_no_temp_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn backup_copies_and_lists_oldest_first() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        let backups_dir = dir.path().join("backups");
+        assert_eq!(backup(&path, &backups_dir).unwrap(), None);
+
+        fs::write(&path, "one").unwrap();
+        let first = backup(&path, &backups_dir).unwrap().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        fs::write(&path, "two").unwrap();
+        let second = backup(&path, &backups_dir).unwrap().unwrap();
+
+        assert_eq!(backups("config.toml", &backups_dir), vec![first.clone(), second]);
+        assert_eq!(fs::read_to_string(first).unwrap(), "one");
+        assert!(backups("settings.json", &backups_dir).is_empty());
+    }
+
+    #[test]
+    fn restore_swaps_in_backup_and_keeps_current() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        let backups_dir = dir.path().join("backups");
+        fs::write(&path, "good").unwrap();
+        let good = backup(&path, &backups_dir).unwrap().unwrap();
+        fs::write(&path, "broken").unwrap();
+
+        let saved = restore(&good, &path, &backups_dir, &dir.path().join("locks")).unwrap().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "good");
+        assert_eq!(fs::read_to_string(saved).unwrap(), "broken");
+    }
+
+    #[test]
+    fn lock_serializes_read_modify_write() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("count");
+        let locks = dir.path().join("locks");
+        fs::write(&path, "0").unwrap();
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let (path, locks) = (path.clone(), locks.clone());
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        let _guard = lock(&path, &locks).unwrap();
+                        let n: u32 = fs::read_to_string(&path).unwrap().parse().unwrap();
+                        write_atomic(&path, (n + 1).to_string().as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "100");
+        // The only lock file is in the locks directory.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+        assert_eq!(fs::read_dir(&locks).unwrap().count(), 1);
+    }
+}
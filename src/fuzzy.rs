@@ -0,0 +1,90 @@
+/// Edit distance between two strings, counted in chars. Swapping two
+/// adjacent chars counts as a single edit, as it is a common typo.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut d = (rows[i - 1][j - 1] + cost)
+                .min(rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d = d.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = d;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+/// Returns the candidate closest to `word`, if it is close enough to be a
+/// plausible misspelling.
+pub fn closest<'a>(word: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let max = (word.chars().count() / 3).max(1);
+    candidates
+        .iter()
+        .map(|c| (levenshtein(word, c), *c))
+        .filter(|(d, _)| *d <= max)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Scores how well `query` matches `text`, ignoring case; lower is better.
+/// 0 means `text` contains `query`, 1 that it contains the query's chars in
+/// order (`stsnd` in "Studio Sounds"), and 2 that one of its words is a
+/// plausible misspelling of `query`. None if it doesn't match at all.
+pub fn score(query: &str, text: &str) -> Option<u32> {
+    let query = query.to_lowercase();
+    let text = text.to_lowercase();
+    if text.contains(&query) {
+        return Some(0);
+    }
+    let mut rest = text.chars();
+    if query.chars().all(|q| rest.any(|c| c == q)) {
+        return Some(1);
+    }
+    let words: Vec<&str> = text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect();
+    closest(&query, &words).map(|_| 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levenshtein_counts_edits() {
+        assert_eq!(levenshtein("volume", "volume"), 0);
+        assert_eq!(levenshtein("volumme", "volume"), 1);
+        assert_eq!(levenshtein("sound_dir", "sounds_dir"), 1);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("dnig", "ding"), 1);
+    }
+
+    #[test]
+    fn closest_suggests_near_match() {
+        let keys = ["sounds_dir", "volume", "events"];
+        assert_eq!(closest("volum", &keys), Some("volume"));
+        assert_eq!(closest("event", &keys), Some("events"));
+    }
+
+    #[test]
+    fn score_ranks_substring_then_subsequence_then_typo() {
+        assert_eq!(score("Sound", "Studio Sounds"), Some(0));
+        assert_eq!(score("stsnd", "Studio Sounds"), Some(1));
+        assert_eq!(score("soudns", "studio sounds"), Some(2));
+        assert_eq!(score("alarm", "Studio Sounds"), None);
+    }
+
+    #[test]
+    fn closest_ignores_distant_words() {
+        let keys = ["sounds_dir", "volume", "events"];
+        assert_eq!(closest("theme", &keys), None);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::config::{self, Config};
+use crate::fsutil;
+use crate::packs::{self, Receipt};
+use crate::settings;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const LOCK_VERSION: u32 = 1;
+
+const LOCK_FILE: &str = "hookplayer.lock";
+
+const HEADER: &str = "# Generated by hookplayer from the installed packs; don't edit by hand.\n\
+                      # Reproduce this set of sounds with 'hookplayer install --locked'.\n\n";
+
+/// `hookplayer.lock`: every installed pack pinned to an exact source and
+/// the sha256 of each of its files.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Lockfile {
+    pub version: u32,
+    #[serde(default)]
+    pub packs: Vec<LockedPack>,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct LockedPack {
+    pub id: String,
+    pub registry: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub source_repo: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub source_path: String,
+    #[serde(default)]
+    pub source_ref: String,
+    /// The commit `source_ref` pointed to at install time. Packs from a
+    /// local directory have none and are pinned by file hashes alone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_dir: Option<String>,
+    pub files: BTreeMap<String, String>,
+}
+
+impl From<Receipt> for LockedPack {
+    fn from(r: Receipt) -> Self {
+        LockedPack {
+            id: r.pack,
+            registry: r.registry,
+            source_repo: r.source_repo,
+            source_path: r.source_path,
+            source_ref: r.source_ref,
+            commit: r.commit,
+            source_dir: r.source_dir,
+            files: r.files,
+        }
+    }
+}
+
+/// `hookplayer.lock`, next to the nearest `.hookplayer.toml` so a project
+/// can share its lockfile, or next to config.toml outside a project.
+pub fn lock_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    match project_lock_path(&std::env::current_dir()?) {
+        Some(path) => Ok(path),
+        None => Ok(config::config_path()?.with_file_name(LOCK_FILE)),
+    }
+}
+
+fn project_lock_path(cwd: &Path) -> Option<PathBuf> {
+    settings::find_project_file(cwd).map(|project| project.with_file_name(LOCK_FILE))
+}
+
+pub fn load() -> Result<Lockfile, Box<dyn std::error::Error>> {
+    let path = lock_path()?;
+    let raw = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let lock: Lockfile = toml::from_str(&raw).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if lock.version > LOCK_VERSION {
+        return Err(format!(
+            "{} was written by a newer hookplayer (lock version {}); run 'hookplayer update'",
+            path.display(),
+            lock.version
+        )
+        .into());
+    }
+    Ok(lock)
+}
+
+/// Builds the lockfile from the receipts of the installed packs.
+pub fn from_installed(cfg: &Config) -> Lockfile {
+    let mut packs: Vec<LockedPack> = packs::installed(cfg)
+        .iter()
+        .filter_map(|dir| Receipt::load(&cfg.sounds_dir_abs().join(dir)))
+        .map(LockedPack::from)
+        .collect();
+    packs.sort_by(|a, b| a.id.cmp(&b.id));
+    Lockfile { version: LOCK_VERSION, packs }
+}
+
+/// Rewrites `hookplayer.lock` to match the installed packs.
+pub fn write(cfg: &Config) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = lock_path()?;
+    let body = HEADER.to_string() + &toml::to_string(&from_installed(cfg))?;
+    let _lock = fsutil::lock(&path, &config::locks_dir()?)?;
+    fsutil::write_atomic(&path, body.as_bytes())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockfile_round_trips_through_toml() {
+        let lock = Lockfile {
+            version: LOCK_VERSION,
+            packs: vec![
+                LockedPack {
+                    id: "peon".into(),
+                    registry: "default".into(),
+                    source_repo: "org/packs".into(),
+                    source_path: "peon".into(),
+                    source_ref: "main".into(),
+                    commit: Some("0123abcd".into()),
+                    files: [("a.wav".to_string(), "aa".to_string())].into_iter().collect(),
+                    ..Default::default()
+                },
+                LockedPack {
+                    id: "mine".into(),
+                    registry: "local".into(),
+                    source_dir: Some("/packs/mine".into()),
+                    ..Default::default()
+                },
+            ],
+        };
+        let raw = toml::to_string(&lock).unwrap();
+        assert!(raw.contains("commit = \"0123abcd\""));
+        assert!(raw.contains("[packs.files]\n\"a.wav\" = \"aa\""));
+        assert_eq!(toml::from_str::<Lockfile>(&raw).unwrap(), lock);
+    }
+
+    #[test]
+    fn lockfile_sits_next_to_the_project_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let nested = dir.path().join("src/app");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert_eq!(project_lock_path(&nested), None);
+
+        std::fs::write(dir.path().join(".hookplayer.toml"), "").unwrap();
+        assert_eq!(project_lock_path(&nested), Some(dir.path().join("hookplayer.lock")));
+    }
+
+    #[test]
+    fn from_installed_reads_receipts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for name in ["b", "a"] {
+            std::fs::create_dir_all(dir.path().join(name)).unwrap();
+            Receipt { pack: name.into(), ..Default::default() }.save(&dir.path().join(name)).unwrap();
+        }
+        std::fs::create_dir_all(dir.path().join("manual")).unwrap();
+
+        let cfg = Config { sounds_dir: dir.path().display().to_string(), ..Default::default() };
+        let ids: Vec<String> = from_installed(&cfg).packs.into_iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+    }
+}
@@ -1,13 +1,39 @@
+mod cache;
+mod check;
 mod config;
+mod events;
+mod fsutil;
+mod fuzzy;
+mod lock;
+mod packs;
 mod player;
+mod progress;
 mod registry;
+mod schedule;
+mod settings;
 mod setup;
+mod sounds;
+mod state;
 mod updater;
 
 use rand::seq::SliceRandom;
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let cli = match settings::take_flags(&mut args) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("hookplayer: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let fetch_mode = match cache::Mode::take_flags(&mut args) {
+        Ok(mode) => mode,
+        Err(e) => {
+            eprintln!("hookplayer: {}", e);
+            std::process::exit(1);
+        }
+    };
     let cmd = args.get(1).map(|s| s.as_str()).unwrap_or("unknown");
 
     match cmd {
@@ -28,28 +54,251 @@ fn main() {
             }
         }
         "list" => {
-            if let Err(e) = registry::list_packs() {
+            let cfg = load_config(&cli);
+            if let Err(e) = registry::list_packs(&cfg, fetch_mode) {
+                eprintln!("hookplayer: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "search" => {
+            let search = match search_args(&args[2..]) {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("hookplayer: {}", e);
+                    eprintln!(
+                        "hookplayer: usage: hookplayer search [query] [--installed|--not-installed] [--category <c>] [--license <l>]"
+                    );
+                    std::process::exit(1);
+                }
+            };
+            let cfg = load_config(&cli);
+            if let Err(e) = registry::search_packs(&cfg, fetch_mode, &search) {
+                eprintln!("hookplayer: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "info" => {
+            let Some(pack_name) = args.get(2) else {
+                eprintln!("hookplayer: usage: hookplayer info <pack>");
+                std::process::exit(1);
+            };
+            let cfg = load_config(&cli);
+            if let Err(e) = registry::pack_info(&cfg, fetch_mode, pack_name) {
                 eprintln!("hookplayer: {}", e);
                 std::process::exit(1);
             }
         }
         "download" => {
-            let pack_name = match args.get(2) {
+            let resume = args.iter().any(|a| a == "--resume");
+            let pack_name = match args[2..].iter().find(|a| !a.starts_with('-')) {
                 Some(n) => n,
                 None => {
-                    eprintln!("hookplayer: usage: hookplayer download <pack>");
+                    eprintln!("hookplayer: usage: hookplayer download <pack> [--resume]");
                     std::process::exit(1);
                 }
             };
-            let cfg = load_config();
-            let sounds_dir = cfg.sounds_dir_abs();
-            if let Err(e) = registry::download_pack(pack_name, &sounds_dir) {
+            let cfg = load_config(&cli);
+            if let Err(e) = registry::download_pack(&cfg, fetch_mode, pack_name, resume) {
+                eprintln!("hookplayer: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "check" => {
+            let json = args.iter().any(|a| a == "--json");
+            match check::run(json, &cli) {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("hookplayer: check failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        "config" if args.get(2).map(|s| s.as_str()) == Some("migrate") => {
+            let dry_run = args.iter().any(|a| a == "--dry-run");
+            if let Err(e) = migrate_config(dry_run) {
+                eprintln!("hookplayer: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "config" => {
+            let usage = "usage: hookplayer config <get|set|unset> <key> [value] | config migrate [--dry-run]";
+            let (action, key) = match (args.get(2), args.get(3)) {
+                (Some(a), Some(k)) => (a.as_str(), k.as_str()),
+                _ => {
+                    eprintln!("hookplayer: {}", usage);
+                    std::process::exit(1);
+                }
+            };
+            let result = match (action, args.get(4)) {
+                ("get", _) => config::get(key).map(|v| match v {
+                    Some(v) => println!("{}", v),
+                    None => {
+                        eprintln!("hookplayer: '{}' is not set", key);
+                        std::process::exit(1);
+                    }
+                }),
+                ("set", Some(value)) => config::set(key, value).map(|_| println!("{} set", key)),
+                ("unset", _) => config::unset(key).map(|removed| {
+                    if removed {
+                        println!("{} unset", key);
+                    } else {
+                        println!("{} was not set", key);
+                    }
+                }),
+                _ => {
+                    eprintln!("hookplayer: {}", usage);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = result {
+                eprintln!("hookplayer: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "event" => {
+            let usage = "usage: hookplayer event <list|add|rm|copy> ...";
+            let cfg = load_config(&cli);
+            let result = match (args.get(2).map(|s| s.as_str()), args.get(3)) {
+                (Some("list"), _) => {
+                    events::list(&cfg);
+                    Ok(())
+                }
+                (Some("add"), Some(event)) if args.len() > 4 => {
+                    events::add(&cfg, event, &args[4..]).map(|added| {
+                        for s in &added {
+                            println!("  + {}", s);
+                        }
+                        println!("{} sound(s) added to '{}'", added.len(), event);
+                    })
+                }
+                (Some("rm"), Some(event)) => {
+                    events::remove(&cfg, event, args.get(4).map(|s| s.as_str())).map(|removed| {
+                        for s in &removed {
+                            println!("  - {}", s);
+                        }
+                        println!("{} sound(s) removed from '{}'", removed.len(), event);
+                    })
+                }
+                (Some("copy"), Some(from)) if args.len() > 4 => {
+                    events::copy(from, &args[4]).map(|n| {
+                        println!("Copied {} sound(s) from '{}' to '{}'", n, from, args[4]);
+                    })
+                }
+                _ => {
+                    eprintln!("hookplayer: {}", usage);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = result {
+                eprintln!("hookplayer: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "resolve" => {
+            let event = match args.get(2) {
+                Some(e) => e,
+                None => {
+                    eprintln!("hookplayer: usage: hookplayer resolve <event>");
+                    std::process::exit(1);
+                }
+            };
+            events::resolve(&load_config(&cli), event);
+        }
+        "schedule" => match args.get(2).map(|s| s.as_str()) {
+            Some("status") | None => schedule::status(&load_config(&cli)),
+            Some(_) => {
+                eprintln!("hookplayer: usage: hookplayer schedule status");
+                std::process::exit(1);
+            }
+        },
+        "volume" => {
+            let cfg = load_config(&cli);
+            let Some(arg) = args.get(2) else {
+                println!("{}", player::describe_level(cfg.volume));
+                return;
+            };
+            // Adjust what config.toml says, not what a project file or
+            // override happens to make it right now.
+            let saved = config::get("volume")
+                .ok()
+                .flatten()
+                .and_then(|v| player::parse_level(&v).ok())
+                .unwrap_or(cfg.volume);
+            let result = player::adjust_volume(saved, arg)
+                .map_err(Into::into)
+                .and_then(|v| config::set("volume", &v.to_string()).map(|_| v));
+            match result {
+                Ok(v) => {
+                    println!("volume set to {}", player::describe_level(v));
+                    if let Ok((effective, _)) = settings::load(&cli)
+                        && effective.volume != v
+                    {
+                        println!("(overridden to {} by a project file, environment or flag)", effective.volume);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("hookplayer: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        "mute" => {
+            let state = state::State { muted: true, allow: allow_list(&args), ..Default::default() };
+            save_state(&state);
+            state::print_status(&state);
+        }
+        "snooze" => {
+            let secs = match args.get(2).map(|d| state::parse_duration(d)) {
+                Some(Ok(secs)) => secs,
+                Some(Err(e)) => {
+                    eprintln!("hookplayer: {}", e);
+                    std::process::exit(1);
+                }
+                None => {
+                    eprintln!("hookplayer: usage: hookplayer snooze <duration> [--allow event,...]");
+                    std::process::exit(1);
+                }
+            };
+            let Some(until) = state::now().checked_add(secs) else {
+                eprintln!("hookplayer: invalid duration '{}' (too long)", args[2]);
+                std::process::exit(1);
+            };
+            let state = state::State {
+                snoozed_until: Some(until),
+                allow: allow_list(&args),
+                ..Default::default()
+            };
+            save_state(&state);
+            state::print_status(&state);
+        }
+        "unmute" => {
+            let state = state::State::default();
+            save_state(&state);
+            state::print_status(&state);
+        }
+        "status" => {
+            state::print_status(&state::State::load());
+            if let Ok((cfg, _)) = settings::load(&cli) {
+                if cfg.mute {
+                    println!("Muted by settings (mute = true).");
+                }
+                let effect = schedule::effect(&cfg, schedule::now());
+                if effect.mute {
+                    println!("Schedule: muted by {}", effect.windows.join(", "));
+                } else if !effect.windows.is_empty() {
+                    println!("Schedule: {} active", effect.windows.join(", "));
+                }
+            }
+        }
+        "restore-backup" => {
+            if let Err(e) = restore_backup(&args[2..]) {
                 eprintln!("hookplayer: {}", e);
                 std::process::exit(1);
             }
         }
         "dir" => {
-            let cfg = load_config();
+            let cfg = load_config(&cli);
             println!("{}", cfg.sounds_dir_abs().display());
         }
         "set-dir" => {
@@ -87,13 +336,15 @@ fn main() {
             };
             let pack_names: Vec<&str> = packs_arg.split(',').map(|s| s.trim()).collect();
             println!("Configuring events from: {}", pack_names.join(", "));
-            match registry::build_events_for_packs(&pack_names) {
+            let cfg = load_config(&cli);
+            match registry::build_events_for_packs(&cfg, fetch_mode, &pack_names) {
                 Ok(events) => {
                     if let Err(e) = config::set_events(&events) {
                         eprintln!("hookplayer: {}", e);
                         std::process::exit(1);
                     }
                     println!("Done. Config updated.");
+                    registry::update_lock(&cfg);
                 }
                 Err(e) => {
                     eprintln!("hookplayer: {}", e);
@@ -101,8 +352,46 @@ fn main() {
                 }
             }
         }
+        "install" => {
+            if !args.iter().any(|a| a == "--locked") {
+                eprintln!("hookplayer: usage: hookplayer install --locked");
+                std::process::exit(1);
+            }
+            let cfg = load_config(&cli);
+            if let Err(e) = registry::install_locked(&cfg, fetch_mode) {
+                eprintln!("hookplayer: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "outdated" => {
+            let cfg = load_config(&cli);
+            if let Err(e) = registry::outdated(&cfg, fetch_mode) {
+                eprintln!("hookplayer: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "upgrade" => {
+            let cfg = load_config(&cli);
+            if let Err(e) = registry::upgrade(&cfg, fetch_mode, args.get(2).map(|s| s.as_str())) {
+                eprintln!("hookplayer: {}", e);
+                std::process::exit(1);
+            }
+        }
+        "remove" => {
+            let keep_config = args.iter().any(|a| a == "--keep-config");
+            let yes = args.iter().any(|a| a == "--yes" || a == "-y");
+            let Some(pack_name) = args[2..].iter().find(|a| !a.starts_with('-')) else {
+                eprintln!("hookplayer: usage: hookplayer remove <pack> [--keep-config] [--yes]");
+                std::process::exit(1);
+            };
+            let cfg = load_config(&cli);
+            if let Err(e) = packs::remove(&cfg, pack_name, keep_config, yes) {
+                eprintln!("hookplayer: {}", e);
+                std::process::exit(1);
+            }
+        }
         "packs" => {
-            let cfg = load_config();
+            let cfg = load_config(&cli);
             let sounds_dir = cfg.sounds_dir_abs();
             match std::fs::read_dir(&sounds_dir) {
                 Ok(entries) => {
@@ -110,6 +399,7 @@ fn main() {
                         .filter_map(|e| e.ok())
                         .filter(|e| e.path().is_dir())
                         .map(|e| e.file_name().to_string_lossy().into_owned())
+                        .filter(|name| !name.starts_with('.'))
                         .collect();
                     names.sort();
                     for name in &names {
@@ -124,7 +414,34 @@ fn main() {
             }
         }
         event => {
-            let cfg = load_config();
+            if state::State::load().silences(event, state::now()) {
+                return;
+            }
+
+            // A broken config must never fail the hook that invoked us.
+            let mut cfg = match settings::load(&cli) {
+                Ok((c, _)) if c.mute => return,
+                Ok((c, _)) => c,
+                Err(e) => {
+                    eprintln!("hookplayer: ignoring event '{}': {}", event, e);
+                    eprintln!("hookplayer: run 'hookplayer check' for details");
+                    return;
+                }
+            };
+            if let Some(profile) = cfg.profile.clone()
+                && let Err(e) = cfg.apply_profile(&profile)
+            {
+                eprintln!("hookplayer: {}", e);
+            }
+            let cap = match schedule::apply(&mut cfg, schedule::now()) {
+                Ok(effect) if effect.mute => return,
+                Ok(effect) => effect.volume,
+                Err(e) => {
+                    eprintln!("hookplayer: schedule: {}", e);
+                    None
+                }
+            };
+
             let sounds = cfg.sounds_for_event(event);
 
             if sounds.is_empty() {
@@ -139,7 +456,13 @@ fn main() {
 
             let chosen = sounds.choose(&mut rand::thread_rng()).unwrap();
 
-            if let Err(e) = player::play(chosen, cfg.volume) {
+            // Like a broken config, an unreachable URL only costs the sound.
+            if let Err(e) = sounds::fetch(chosen) {
+                eprintln!("hookplayer: could not fetch {}: {}", chosen.url.as_deref().unwrap_or_default(), e);
+                return;
+            }
+
+            if let Err(e) = player::play_clip(chosen, player::Gain { cap, ..cfg.gain(event) }, &cfg.output()) {
                 eprintln!("hookplayer: playback error: {}", e);
                 std::process::exit(1);
             }
@@ -147,9 +470,132 @@ fn main() {
     }
 }
 
-fn load_config() -> config::Config {
-    match config::Config::load() {
-        Ok(c) => c,
+/// Upgrades config.toml to the current schema, or with `dry_run` prints the
+/// changes that would be made.
+fn migrate_config(dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let path = config::config_path()?;
+    let raw = std::fs::read_to_string(&path)?;
+    let Some(migration) = config::migrate(&path, &raw, config::MIGRATIONS)? else {
+        println!("{} is already at version {}", path.display(), config::CONFIG_VERSION);
+        return Ok(());
+    };
+    if dry_run {
+        let name = path.display();
+        print!(
+            "{}",
+            config::diff(
+                &format!("{} (version {})", name, migration.from),
+                &raw,
+                &format!("{} (version {})", name, migration.to),
+                &migration.output,
+            )
+        );
+        return Ok(());
+    }
+    config::read_config(&path)?;
+    Ok(())
+}
+
+/// `restore-backup [config|settings|<file.bak>] [--list]`: lists backups, or
+/// puts the newest one (or the one given) back in place.
+fn restore_backup(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let backups_dir = config::backups_dir()?;
+    let targets = [config::config_path()?, setup::settings_path()];
+    let target_named = |name: &str| targets.iter().find(|t| t.file_name().is_some_and(|f| f == name)).cloned();
+
+    let which = args.iter().find(|a| !a.starts_with("--")).map(|s| s.as_str());
+    let (target, chosen) = match which {
+        None | Some("config") => (targets[0].clone(), None),
+        Some("settings") => (targets[1].clone(), None),
+        Some(file) => {
+            let file = std::path::PathBuf::from(file);
+            let file = if file.is_file() { file } else { backups_dir.join(file) };
+            let name = file.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            let target = ["config.toml", "settings.json"]
+                .iter()
+                .find(|t| name.starts_with(&format!("{}.", t)))
+                .and_then(|t| target_named(t))
+                .ok_or_else(|| format!("'{}' is not a hookplayer backup", name))?;
+            (target, Some(file))
+        }
+    };
+    let name = target.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let available = fsutil::backups(&name, &backups_dir);
+
+    if args.iter().any(|a| a == "--list") {
+        for b in available.iter().rev() {
+            println!("  {}", b.file_name().unwrap_or_default().to_string_lossy());
+        }
+        println!("\n{} backup(s) of {} in {}", available.len(), name, backups_dir.display());
+        return Ok(());
+    }
+
+    let chosen = match chosen {
+        Some(file) => file,
+        None => available.last().cloned().ok_or_else(|| format!("no backups of {} found", name))?,
+    };
+    let saved = fsutil::restore(&chosen, &target, &backups_dir, &config::locks_dir()?)?;
+    println!("Restored {} from {}", target.display(), chosen.display());
+    if let Some(saved) = saved {
+        println!("The version it replaced was saved as {}", saved.display());
+    }
+    Ok(())
+}
+
+/// Reads `--allow a,b` (or `--allow=a,b`) into a list of event names.
+fn allow_list(args: &[String]) -> Vec<String> {
+    let value = args.iter().enumerate().find_map(|(i, a)| match a.strip_prefix("--allow") {
+        Some("") => args.get(i + 1).cloned(),
+        Some(rest) => rest.strip_prefix('=').map(String::from),
+        None => None,
+    });
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Parses `search` arguments: filters, with the remaining words as the query.
+fn search_args(args: &[String]) -> Result<registry::Search, String> {
+    let mut search = registry::Search::default();
+    let mut words = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || inline.clone().or_else(|| iter.next().cloned()).ok_or(format!("{} needs a value", flag));
+        match flag {
+            "--installed" => search.installed = Some(true),
+            "--not-installed" => search.installed = Some(false),
+            "--category" => search.category = Some(value()?),
+            "--license" => search.license = Some(value()?),
+            _ if flag.starts_with("--") => return Err(format!("unknown option '{}'", flag)),
+            _ => words.push(arg.as_str()),
+        }
+    }
+    search.query = words.join(" ");
+    Ok(search)
+}
+
+fn save_state(state: &state::State) {
+    if let Err(e) = state.save() {
+        eprintln!("hookplayer: could not save state: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn load_config(cli: &toml::Table) -> config::Config {
+    match settings::load(cli) {
+        Ok((c, warnings)) => {
+            for w in warnings {
+                eprintln!("hookplayer: warning: {}", w);
+            }
+            c
+        }
         Err(e) => {
             eprintln!("hookplayer: failed to load config: {}", e);
             std::process::exit(1);
@@ -0,0 +1,262 @@
+use crate::config::{self, Config};
+use crate::{fsutil, sounds};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// The file in each pack directory recording what was installed.
+pub const RECEIPT_FILE: &str = ".hookplayer-receipt.json";
+
+/// What `download` installed into a pack directory, so later runs can tell
+/// whether the registry's copy has changed and which files need fetching.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Receipt {
+    /// The registry id the pack was installed as.
+    pub pack: String,
+    pub registry: String,
+    pub source_repo: String,
+    pub source_ref: String,
+    pub source_path: String,
+    /// The commit `source_ref` resolved to when the pack was installed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_dir: Option<String>,
+    /// The manifest's own version, if it has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub manifest_sha256: String,
+    /// Unix time of the install.
+    pub installed_at: u64,
+    /// Installed file names and their sha256.
+    pub files: BTreeMap<String, String>,
+}
+
+impl Receipt {
+    /// Reads the receipt in `dir`. None if the pack was installed without
+    /// one, or by hand.
+    pub fn load(dir: &Path) -> Option<Receipt> {
+        let raw = std::fs::read_to_string(dir.join(RECEIPT_FILE)).ok()?;
+        serde_json::from_str(&raw).ok()
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let json = serde_json::to_string_pretty(self)? + "\n";
+        fsutil::write_atomic(&dir.join(RECEIPT_FILE), json.as_bytes())?;
+        Ok(())
+    }
+
+    /// The recorded sha256 of `name`, if the file on disk still matches it.
+    pub fn unchanged(&self, dir: &Path, name: &str) -> Option<&str> {
+        let recorded = self.files.get(name)?;
+        let bytes = std::fs::read(dir.join(name)).ok()?;
+        (sha256_hex(&bytes) == *recorded).then_some(recorded.as_str())
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Installed pack directories under sounds_dir, sorted by name. Hidden
+/// directories, such as the staging area, aren't packs.
+pub fn installed(cfg: &Config) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(cfg.sounds_dir_abs()) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Where a pack is assembled before it replaces the installed copy, so a
+/// failed download never leaves a half-filled pack in sounds_dir. Partial
+/// downloads stay here for `download --resume`.
+pub fn staging_dir(cfg: &Config, dir_name: &str) -> PathBuf {
+    cfg.sounds_dir_abs().join(".staging").join(dir_name)
+}
+
+/// Puts an installed file into a staging directory without downloading it
+/// again, sharing the data where the filesystem allows.
+pub fn seed(from: &Path, to: &Path) -> io::Result<()> {
+    std::fs::hard_link(from, to).or_else(|_| std::fs::copy(from, to).map(|_| ()))
+}
+
+/// Moves a completed staging directory into place as `dir`. The previous
+/// copy is put aside first and restored if the move fails.
+pub fn promote(stage: &Path, dir: &Path) -> io::Result<()> {
+    let mut old = stage.as_os_str().to_owned();
+    old.push(".old");
+    let old = PathBuf::from(old);
+    let _ = std::fs::remove_dir_all(&old);
+    if dir.exists() {
+        std::fs::rename(dir, &old)?;
+    }
+    if let Err(e) = std::fs::rename(stage, dir) {
+        let _ = std::fs::rename(&old, dir);
+        return Err(e);
+    }
+    let _ = std::fs::remove_dir_all(&old);
+    Ok(())
+}
+
+/// Checks that `name` is a single plain path component, so joining it
+/// onto a directory can't reach outside it.
+pub fn check_name(name: &str) -> Result<(), String> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !name.contains('/') => Ok(()),
+        _ => Err(format!("invalid name '{}'", name)),
+    }
+}
+
+/// The directory an installed pack lives in. Accepts the directory name or
+/// a registry id such as `team/alerts`.
+fn pack_dir(cfg: &Config, name: &str) -> Result<PathBuf, String> {
+    let dir_name = name.trim_end_matches('/').replace('/', "-");
+    check_name(&dir_name).map_err(|_| format!("invalid pack name '{}'", name))?;
+    Ok(cfg.sounds_dir_abs().join(dir_name))
+}
+
+/// Whether an event entry points into `dir`, however it is written.
+fn refers_to(entry: &str, sounds_dir: &Path, dir: &Path) -> bool {
+    if entry.starts_with('!') || entry.contains("://") {
+        return false;
+    }
+    let (root, rest) = sounds::locate(sounds_dir, entry);
+    root.join(rest).starts_with(dir)
+}
+
+/// Deletes an installed pack and, unless `keep_config`, strips its sounds
+/// from every event. Asks first unless `yes`.
+pub fn remove(cfg: &Config, name: &str, keep_config: bool, yes: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = pack_dir(cfg, name)?;
+    let sounds_dir = cfg.sounds_dir_abs();
+    let matches = |entry: &str| refers_to(entry, &sounds_dir, &dir);
+    let entries = if keep_config { Vec::new() } else { config::strip_sounds(matches, true)? };
+    let installed = dir.is_dir();
+
+    if !installed && entries.is_empty() {
+        return Err(format!("pack '{}' is not installed in {}", name, sounds_dir.display()).into());
+    }
+
+    if !yes {
+        println!();
+        if installed {
+            let files = std::fs::read_dir(&dir)?.count();
+            println!("  delete {} ({} file(s))", dir.display(), files);
+        }
+        for (event, sound) in &entries {
+            println!("  remove {} from '{}'", sound, event);
+        }
+        print!("\nProceed? [y/N] ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        if !line.trim().eq_ignore_ascii_case("y") {
+            println!("Aborted.");
+            return Ok(());
+        }
+    }
+
+    if let Some(dir_name) = dir.file_name() {
+        let _ = std::fs::remove_dir_all(staging_dir(cfg, &dir_name.to_string_lossy()));
+    }
+    if installed {
+        std::fs::remove_dir_all(&dir)?;
+        println!("Deleted {}", dir.display());
+        crate::registry::update_lock(cfg);
+    }
+    if !keep_config {
+        let removed = config::strip_sounds(matches, false)?;
+        for (event, sound) in &removed {
+            println!("  - {} ({})", sound, event);
+        }
+        println!("{} sound(s) removed from config", removed.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refers_to_matches_entries_inside_the_pack() {
+        let sounds = Path::new("/s");
+        let dir = Path::new("/s/peon");
+        assert!(refers_to("peon/a.wav", sounds, dir));
+        assert!(refers_to("peon/*", sounds, dir));
+        assert!(refers_to("/s/peon/a.wav", sounds, dir));
+        assert!(!refers_to("peon-extra/a.wav", sounds, dir));
+        assert!(!refers_to("!peon", sounds, dir));
+        assert!(!refers_to("https://example.com/peon/a.wav", sounds, dir));
+    }
+
+    #[test]
+    fn receipt_detects_unchanged_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("a.wav"), "one").unwrap();
+        std::fs::write(dir.path().join("b.wav"), "edited").unwrap();
+        let receipt = Receipt {
+            pack: "peon".into(),
+            files: [("a.wav", "one"), ("b.wav", "two"), ("c.wav", "three")]
+                .into_iter()
+                .map(|(name, body)| (name.to_string(), sha256_hex(body.as_bytes())))
+                .collect(),
+            ..Default::default()
+        };
+        receipt.save(dir.path()).unwrap();
+
+        let loaded = Receipt::load(dir.path()).unwrap();
+        assert_eq!(loaded, receipt);
+        assert_eq!(loaded.unchanged(dir.path(), "a.wav"), Some(sha256_hex(b"one").as_str()));
+        assert_eq!(loaded.unchanged(dir.path(), "b.wav"), None);
+        assert_eq!(loaded.unchanged(dir.path(), "c.wav"), None);
+    }
+
+    #[test]
+    fn promote_replaces_the_installed_copy() {
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config { sounds_dir: root.path().display().to_string(), ..Default::default() };
+        let dir = root.path().join("peon");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("old.wav"), "old").unwrap();
+
+        let stage = staging_dir(&cfg, "peon");
+        std::fs::create_dir_all(&stage).unwrap();
+        seed(&dir.join("old.wav"), &stage.join("kept.wav")).unwrap();
+        std::fs::write(stage.join("new.wav"), "new").unwrap();
+        promote(&stage, &dir).unwrap();
+
+        let mut files: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["kept.wav", "new.wav"]);
+        assert!(!stage.exists());
+        assert_eq!(installed(&cfg), vec!["peon"]);
+    }
+
+    #[test]
+    fn pack_dir_rejects_paths() {
+        let cfg = Config { sounds_dir: "/s".into(), ..Default::default() };
+        assert_eq!(pack_dir(&cfg, "team/alerts").unwrap(), Path::new("/s/team-alerts"));
+        assert!(pack_dir(&cfg, "..").is_err());
+        assert!(pack_dir(&cfg, "").is_err());
+        assert!(check_name("a.wav").is_ok());
+        for bad in ["..", ".", "", "/etc", "a/b", "../a.wav", "a/"] {
+            assert!(check_name(bad).is_err(), "{}", bad);
+        }
+    }
+}
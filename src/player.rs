@@ -1,7 +1,10 @@
-use rodio::{Decoder, OutputStream, Sink};
+use crate::sounds::Clip;
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source, cpal};
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
+use std::time::Duration;
 
 // PORT: PLAYER
 // Swap this function to change the audio backend. Only the signature is contractual.
@@ -20,3 +23,251 @@ pub fn play(path: &Path, volume: f32) -> Result<(), Box<dyn std::error::Error>>
 
     Ok(())
 }
+
+/// The gain pipeline. Every clip plays at
+///
+/// ```text
+/// master × profile × event × sound
+/// ```
+///
+/// where `master` is the config's `volume`, `profile` the active profile's
+/// `volume`, `event` the `[event_volume]` entry for the event and `sound` the
+/// clip's own `gain`. The product is clamped to 0.0..=1.0 and then limited by
+/// the cap of any active schedule window. Each factor is linear; dB values
+/// from the config are converted by `parse_level` before they get here.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gain {
+    pub master: f32,
+    pub profile: f32,
+    pub event: f32,
+    pub cap: Option<f32>,
+}
+
+impl Gain {
+    /// The output volume for a clip with the given per-sound gain.
+    pub fn level(&self, sound: f32) -> f32 {
+        let level = self.master * self.profile * self.event * sound;
+        let level = if level.is_nan() { 0.0 } else { level.clamp(0.0, 1.0) };
+        self.cap.map_or(level, |cap| level.min(cap))
+    }
+}
+
+/// Parses a volume or gain: a linear factor (`0.5`), a percentage (`50%`)
+/// or decibels relative to full scale (`-6dB`).
+pub fn parse_level(s: &str) -> Result<f32, String> {
+    let s = s.trim();
+    let invalid = || format!("invalid volume '{}' (expected e.g. 0.5, 50% or -6dB)", s);
+    let lower = s.to_ascii_lowercase();
+    let level = if let Some(db) = lower.strip_suffix("db") {
+        db_to_linear(db.trim().parse().map_err(|_| invalid())?)
+    } else if let Some(pct) = s.strip_suffix('%') {
+        pct.trim().parse::<f32>().map_err(|_| invalid())? / 100.0
+    } else {
+        s.parse().map_err(|_| invalid())?
+    };
+    if !level.is_finite() || level < 0.0 {
+        return Err(invalid());
+    }
+    Ok(level)
+}
+
+/// Computes a new master volume from `hookplayer volume <arg>`. Signed
+/// arguments are relative: `+10%` adds ten points, `-3dB` scales by -3 dB.
+/// Relative changes are clamped to 0.0..=1.0; absolute values outside that
+/// range are rejected. The result is rounded to three decimals.
+pub fn adjust_volume(current: f32, arg: &str) -> Result<f32, String> {
+    let arg = arg.trim();
+    let next = match arg.strip_prefix('+').map(|a| (1.0, a)).or(arg.strip_prefix('-').map(|a| (-1.0, a))) {
+        Some((sign, delta)) if delta.to_ascii_lowercase().ends_with("db") => {
+            let db: f32 = delta[..delta.len() - 2]
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid volume change '{}'", arg))?;
+            (current * db_to_linear(sign * db)).clamp(0.0, 1.0)
+        }
+        Some((sign, delta)) => {
+            let step = parse_level(delta).map_err(|_| format!("invalid volume change '{}'", arg))?;
+            (current + sign * step).clamp(0.0, 1.0)
+        }
+        None => {
+            let level = parse_level(arg)?;
+            if level > 1.0 {
+                return Err(format!("volume {} is out of range (expected 0.0 to 1.0)", arg));
+            }
+            level
+        }
+    };
+    Ok((next * 1000.0).round() / 1000.0)
+}
+
+/// Formats a level as `0.5 (50%, -6.0 dB)`.
+pub fn describe_level(level: f32) -> String {
+    if level <= 0.0 {
+        return format!("{} (silent)", level);
+    }
+    format!("{} ({:.0}%, {:.1} dB)", level, level * 100.0, linear_to_db(level))
+}
+
+pub fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+pub fn linear_to_db(level: f32) -> f32 {
+    20.0 * level.log10()
+}
+
+/// Where to play: an audio backend (cpal's host, e.g. `alsa` or `jack`)
+/// and an output device on it. `None` means the system default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Output {
+    pub backend: Option<String>,
+    pub device: Option<String>,
+}
+
+/// Plays a clip at the level `gain` gives it. Trimmed clips and clips sent
+/// to a chosen output go through rodio directly; the rest go through `play`
+/// so a swapped-in backend still handles them.
+pub fn play_clip(clip: &Clip, gain: Gain, output: &Output) -> Result<(), Box<dyn std::error::Error>> {
+    let volume = gain.level(clip.gain);
+    if clip.start.is_none() && clip.end.is_none() && *output == Output::default() {
+        return play(&clip.path, volume);
+    }
+
+    let file = BufReader::new(File::open(&clip.path)?);
+    let start = Duration::from_secs_f32(clip.start.unwrap_or(0.0));
+    let source = Decoder::new(file)?.skip_duration(start);
+
+    let (_stream, stream_handle) = open_stream(output)?;
+    let sink = Sink::try_new(&stream_handle)?;
+    sink.set_volume(volume);
+    match clip.end {
+        Some(end) => sink.append(source.take_duration(Duration::from_secs_f32(end).saturating_sub(start))),
+        None => sink.append(source),
+    }
+    sink.sleep_until_end();
+
+    Ok(())
+}
+
+fn open_stream(output: &Output) -> Result<(OutputStream, OutputStreamHandle), Box<dyn std::error::Error>> {
+    if *output == Output::default() {
+        return Ok(OutputStream::try_default()?);
+    }
+
+    let host = match &output.backend {
+        Some(name) => {
+            let hosts = cpal::available_hosts();
+            let id = hosts
+                .iter()
+                .find(|id| id.name().eq_ignore_ascii_case(name))
+                .ok_or_else(|| {
+                    let names: Vec<&str> = hosts.iter().map(|id| id.name()).collect();
+                    format!("unknown audio backend '{}' (available: {})", name, names.join(", "))
+                })?;
+            cpal::host_from_id(*id)?
+        }
+        None => cpal::default_host(),
+    };
+    let device = match &output.device {
+        Some(name) => host
+            .output_devices()?
+            .find(|d| d.name().is_ok_and(|n| n == *name))
+            .ok_or_else(|| format!("output device '{}' not found", name))?,
+        None => host.default_output_device().ok_or("no default output device")?,
+    };
+    Ok(OutputStream::try_from_device(&device)?)
+}
+
+/// Opens and decodes the start of a file without playing it, to confirm the
+/// codec is supported by the backend.
+pub fn probe(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let file = BufReader::new(File::open(path)?);
+    Decoder::new(file)?;
+    Ok(())
+}
+
+/// Like `probe`, for audio that hasn't been written to disk yet.
+pub fn probe_bytes(bytes: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+    Decoder::new(std::io::Cursor::new(bytes))?;
+    Ok(())
+}
+
+/// A 16-bit mono WAV of `samples` silent samples, for tests that need a
+/// file the decoder accepts.
+#[cfg(test)]
+pub fn silent_wav(samples: usize) -> Vec<u8> {
+    let data = vec![0u8; samples * 2];
+    let mut wav = Vec::new();
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&8000u32.to_le_bytes());
+    wav.extend_from_slice(&16000u32.to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&data);
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gain(master: f32, profile: f32, event: f32) -> Gain {
+        Gain { master, profile, event, cap: None }
+    }
+
+    #[test]
+    fn level_multiplies_every_stage() {
+        assert_eq!(gain(0.5, 0.5, 0.8).level(0.5), 0.1);
+        assert_eq!(gain(1.0, 1.0, 1.0).level(1.0), 1.0);
+    }
+
+    #[test]
+    fn level_is_clamped_then_capped() {
+        assert_eq!(gain(0.8, 1.0, 1.0).level(2.0), 1.0);
+        assert_eq!(gain(f32::NAN, 1.0, 1.0).level(1.0), 0.0);
+        let capped = Gain { cap: Some(0.3), ..gain(0.8, 1.0, 1.0) };
+        assert_eq!(capped.level(1.0), 0.3);
+        assert_eq!(capped.level(0.25), 0.2);
+    }
+
+    #[test]
+    fn parse_level_accepts_linear_percent_and_db() {
+        assert_eq!(parse_level("0.5"), Ok(0.5));
+        assert_eq!(parse_level("75%"), Ok(0.75));
+        assert_eq!(parse_level("0dB"), Ok(1.0));
+        let half = parse_level("-6 dB").unwrap();
+        assert!((half - 0.501).abs() < 0.001, "{}", half);
+    }
+
+    #[test]
+    fn adjust_volume_absolute_and_relative() {
+        assert_eq!(adjust_volume(0.5, "0.7"), Ok(0.7));
+        assert_eq!(adjust_volume(0.5, "30%"), Ok(0.3));
+        assert_eq!(adjust_volume(0.5, "+10%"), Ok(0.6));
+        assert_eq!(adjust_volume(0.5, "-0.2"), Ok(0.3));
+        assert_eq!(adjust_volume(0.5, "-6dB"), Ok(0.251));
+        assert_eq!(adjust_volume(0.95, "+10%"), Ok(1.0));
+        assert_eq!(adjust_volume(0.05, "-10%"), Ok(0.0));
+    }
+
+    #[test]
+    fn adjust_volume_rejects_out_of_range_and_garbage() {
+        assert!(adjust_volume(0.5, "1.5").is_err());
+        assert!(adjust_volume(0.5, "+loud").is_err());
+        assert!(adjust_volume(0.5, "-xdB").is_err());
+    }
+
+    #[test]
+    fn parse_level_rejects_garbage_and_negatives() {
+        for bad in ["loud", "-0.5", "-10%", "dB", ""] {
+            assert!(parse_level(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+const BAR_WIDTH: usize = 24;
+
+/// Reports download progress: a single redrawn bar with files, bytes and
+/// an ETA when stdout is a terminal, or one line per file otherwise so
+/// logs stay readable.
+pub struct Progress {
+    total: usize,
+    done: usize,
+    bytes: u64,
+    started: Instant,
+    tty: bool,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Progress {
+        Progress { total, done: 0, bytes: 0, started: Instant::now(), tty: std::io::stdout().is_terminal() }
+    }
+
+    /// Records a finished file. Failures are always printed on their own
+    /// line; successes only without a bar.
+    pub fn file_done(&mut self, name: &str, bytes: u64, ok: bool) {
+        self.done += 1;
+        self.bytes += bytes;
+        let mark = if ok { '+' } else { '!' };
+        if !self.tty {
+            println!("  {} {}", mark, name);
+            return;
+        }
+        if !ok {
+            print!("\r\x1b[2K  {} {}\n", mark, name);
+        }
+        print!("\r\x1b[2K{}", self.line(self.started.elapsed()));
+        let _ = std::io::stdout().flush();
+    }
+
+    /// Clears the bar, leaving the terminal ready for the summary.
+    pub fn finish(&self) {
+        if self.tty && self.total > 0 {
+            print!("\r\x1b[2K");
+            let _ = std::io::stdout().flush();
+        }
+    }
+
+    fn line(&self, elapsed: Duration) -> String {
+        let filled = (self.done * BAR_WIDTH).checked_div(self.total).unwrap_or(BAR_WIDTH);
+        let mut line = format!(
+            "[{}{}] {}/{} files, {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.done,
+            self.total,
+            describe_size(self.bytes)
+        );
+        if self.done > 0 && self.done < self.total {
+            let remaining = elapsed.mul_f64((self.total - self.done) as f64 / self.done as f64);
+            line += &format!(", ETA {}", describe_duration(remaining));
+        }
+        line
+    }
+}
+
+pub fn describe_size(bytes: u64) -> String {
+    match bytes {
+        b if b < 1024 => format!("{} B", b),
+        b if b < 1024 * 1024 => format!("{:.1} KB", b as f64 / 1024.0),
+        b => format!("{:.1} MB", b as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+fn describe_duration(d: Duration) -> String {
+    let secs = d.as_secs_f64().ceil() as u64;
+    if secs < 60 {
+        format!("{}s", secs)
+    } else {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_size_picks_units() {
+        assert_eq!(describe_size(512), "512 B");
+        assert_eq!(describe_size(1536), "1.5 KB");
+        assert_eq!(describe_size(3 * 1024 * 1024), "3.0 MB");
+    }
+
+    #[test]
+    fn line_shows_files_bytes_and_eta() {
+        let mut progress = Progress { total: 4, done: 1, bytes: 2048, started: Instant::now(), tty: true };
+        assert_eq!(
+            progress.line(Duration::from_secs(5)),
+            "[######------------------] 1/4 files, 2.0 KB, ETA 15s"
+        );
+        progress.done = 4;
+        assert_eq!(progress.line(Duration::from_secs(100)), "[########################] 4/4 files, 2.0 KB");
+        assert_eq!(describe_duration(Duration::from_secs(65)), "1m05s");
+    }
+}
@@ -1,20 +1,82 @@
+use crate::cache::{self, Cache};
+use crate::config::{self, Config};
+use crate::lock;
+use crate::packs::{self, Receipt};
+use crate::progress::{self, Progress};
+use crate::{fsutil, fuzzy, player};
 use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
-use std::io::Write;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
 // PORT: REGISTRY_URL
-// Change this to point at your own pack registry.
+// Change this to point at your own pack registry, or override it per machine
+// with `registry_url` in the config or HOOKPLAYER_REGISTRY_URL.
 // The endpoint must return JSON shaped as: { "packs": [ { name, display_name, source_repo, source_ref, source_path } ] }
-const REGISTRY_URL: &str = "https://peonping.github.io/registry/index.json";
+pub const REGISTRY_URL: &str = "https://peonping.github.io/registry/index.json";
 
-#[derive(Debug, Deserialize)]
+/// The name `registry_url` goes by next to the `registries` list.
+pub const DEFAULT_REGISTRY: &str = "default";
+
+/// The name packs from `registry.d` files go by. They are merged ahead of
+/// every remote registry.
+pub const LOCAL_REGISTRY: &str = "local";
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct RegistryPack {
     pub name: String,
     pub display_name: String,
+    #[serde(default)]
     pub source_repo: String,
+    #[serde(default = "default_ref")]
     pub source_ref: String,
+    #[serde(default)]
     pub source_path: String,
+    /// A local directory holding the pack (its `openpeon.json` and
+    /// `sounds/`), used instead of a repo. Only read from `registry.d`.
+    #[serde(default)]
+    pub source_dir: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub license: Option<String>,
+    /// The openpeon categories the pack has sounds for.
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub total_size_bytes: Option<u64>,
+    /// The name this pack is addressed by: `name`, or `registry/name` when
+    /// an earlier registry already has a pack called `name`.
+    #[serde(skip)]
+    pub id: String,
+    /// The registry the pack came from.
+    #[serde(skip)]
+    pub registry: String,
+}
+
+impl RegistryPack {
+    /// The directory the pack installs into under sounds_dir. Namespaced
+    /// packs use `registry-name` so they don't clash with the bare name.
+    /// Fails for names that would land outside sounds_dir.
+    pub fn dir_name(&self) -> Result<String, String> {
+        let dir_name = self.id.replace('/', "-");
+        packs::check_name(&self.name)
+            .and_then(|()| packs::check_name(&dir_name))
+            .map_err(|_| format!("invalid pack name '{}'", self.id))?;
+        Ok(dir_name)
+    }
+
+    /// The same pack, fetched at `rev` instead of its `source_ref`.
+    fn at(&self, rev: &str) -> RegistryPack {
+        RegistryPack { source_ref: rev.to_string(), ..self.clone() }
+    }
+}
+
+fn default_ref() -> String {
+    "main".to_string()
 }
 
 #[derive(Deserialize)]
@@ -24,7 +86,14 @@ struct Registry {
 
 #[derive(Deserialize)]
 struct Manifest {
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    license: Option<String>,
     categories: HashMap<String, Category>,
+    /// Hash of the manifest file itself, to tell when a pack has changed.
+    #[serde(skip)]
+    sha256: String,
 }
 
 #[derive(Deserialize)]
@@ -35,89 +104,858 @@ struct Category {
 #[derive(Deserialize)]
 struct SoundEntry {
     file: String,
+    #[serde(default)]
+    sha256: Option<String>,
+}
+
+impl Manifest {
+    /// The unique sound file names the pack installs, with their expected
+    /// sha256 where the manifest gives one.
+    fn files(&self) -> BTreeMap<String, Option<String>> {
+        let mut files = BTreeMap::new();
+        for category in self.categories.values() {
+            for sound in &category.sounds {
+                if let Some(basename) = Path::new(&sound.file).file_name() {
+                    files.insert(basename.to_string_lossy().into_owned(), sound.sha256.clone());
+                }
+            }
+        }
+        files
+    }
 }
 
-pub fn fetch_registry() -> Result<Vec<RegistryPack>, Box<dyn std::error::Error>> {
-    let resp = reqwest::blocking::get(REGISTRY_URL)?;
-    let registry: Registry = resp.json()?;
-    Ok(registry.packs)
+/// Fetches registry indexes and pack files. Indexes and manifests go
+/// through the cache; `token` authenticates requests for private repos.
+struct Fetcher<'a> {
+    client: reqwest::blocking::Client,
+    token: Option<&'a str>,
+    cache: Cache,
+    mode: cache::Mode,
 }
 
-pub fn list_packs() -> Result<(), Box<dyn std::error::Error>> {
-    let packs = fetch_registry()?;
+impl<'a> Fetcher<'a> {
+    fn new(cfg: &'a Config, mode: cache::Mode) -> Result<Fetcher<'a>, reqwest::Error> {
+        Ok(Fetcher {
+            client: reqwest::blocking::Client::builder().user_agent("hookplayer").build()?,
+            token: cfg.github_token.as_deref(),
+            cache: Cache::new(config::cache_dir().join("registry"), mode),
+            mode,
+        })
+    }
+
+    fn registry(&self, url: &str) -> Result<Vec<RegistryPack>, Box<dyn std::error::Error>> {
+        let registry: Registry = serde_json::from_slice(&self.cache.get(&self.client, None, url)?)?;
+        Ok(registry.packs)
+    }
+
+    fn manifest(&self, pack: &RegistryPack) -> Result<Manifest, Box<dyn std::error::Error>> {
+        let raw = match pack.source_dir {
+            Some(_) => pack_file(pack, "openpeon.json")?,
+            None => self.cache.get(&self.client, self.token, &format!("{}/openpeon.json", pack_base_url(pack)))?,
+        };
+        let mut manifest: Manifest = serde_json::from_slice(&raw)?;
+        manifest.sha256 = packs::sha256_hex(&raw);
+        Ok(manifest)
+    }
+
+    /// The commit a repo pack's `source_ref` currently points to. None for
+    /// local packs, when offline, or if GitHub can't be asked.
+    fn commit(&self, pack: &RegistryPack) -> Option<String> {
+        if pack.source_dir.is_some() || self.mode == cache::Mode::Offline {
+            return None;
+        }
+        let url = format!("https://api.github.com/repos/{}/commits/{}", pack.source_repo, pack.source_ref);
+        let mut request = self.client.get(&url).header(reqwest::header::ACCEPT, "application/vnd.github.sha");
+        if let Some(token) = self.token {
+            request = request.bearer_auth(token);
+        }
+        match request.send().and_then(|r| r.error_for_status()).and_then(|r| r.text()) {
+            Ok(sha) => Some(sha.trim().to_string()),
+            Err(e) => {
+                eprintln!("hookplayer: warning: could not resolve {}@{}: {}", pack.source_repo, pack.source_ref, e);
+                None
+            }
+        }
+    }
+
+    /// Reads an uncached file, such as a sound, from wherever a pack lives.
+    fn file(&self, pack: &RegistryPack, rel: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if pack.source_dir.is_some() {
+            return pack_file(pack, rel);
+        }
+        if self.mode == cache::Mode::Offline {
+            return Err(format!("can't download '{}' while offline", pack.id).into());
+        }
+        let response = self.get_with_retries(&format!("{}/{}", pack_base_url(pack), rel))?;
+        let content_type = response.headers().get(reqwest::header::CONTENT_TYPE).and_then(|v| v.to_str().ok());
+        if let Some(kind) = content_type
+            && is_page(kind)
+        {
+            return Err(format!("server sent {} instead of audio", kind).into());
+        }
+        Ok(response.bytes()?.to_vec())
+    }
+
+    /// GETs `url`, retrying with backoff when the failure looks temporary.
+    fn get_with_retries(&self, url: &str) -> Result<reqwest::blocking::Response, reqwest::Error> {
+        let mut attempt = 1;
+        loop {
+            let mut request = self.client.get(url);
+            if let Some(token) = self.token {
+                request = request.bearer_auth(token);
+            }
+            match request.send().and_then(|r| r.error_for_status()) {
+                Err(e) if attempt < ATTEMPTS && is_transient(&e) => {
+                    std::thread::sleep(retry_delay(attempt));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// How many times a pack file is requested before giving up.
+const ATTEMPTS: u32 = 3;
+
+fn retry_delay(attempt: u32) -> Duration {
+    Duration::from_millis(500 * 4u64.pow(attempt - 1))
+}
+
+/// Timeouts, dropped connections, rate limits and server errors are worth
+/// another try; anything else will fail the same way again.
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_timeout() || e.is_connect() || e.status().is_some_and(transient_status)
+}
+
+fn transient_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+/// The result of fetching one file: its bytes and sha256, or why it was
+/// rejected along with whatever was downloaded.
+type Outcome = Result<(Vec<u8>, String), (String, Option<Vec<u8>>)>;
+
+/// Fetches `files` from `pack` on up to `jobs` threads. Each download is
+/// checked by `check` on its worker, which returns the file's sha256; `done`
+/// then runs on the calling thread as each file finishes. If `done` fails,
+/// no further downloads are started.
+fn fetch_files(
+    fetcher: &Fetcher,
+    pack: &RegistryPack,
+    files: &[(String, Option<String>)],
+    jobs: usize,
+    check: impl Fn(&[u8], Option<&str>) -> Result<String, String> + Sync,
+    mut done: impl FnMut(&str, Outcome) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, files.len().max(1)) {
+            let (tx, next, check) = (tx.clone(), &next, &check);
+            scope.spawn(move || {
+                while let Some((name, expected)) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let outcome = match fetcher.file(pack, &format!("sounds/{}", name)) {
+                        Ok(bytes) => match check(&bytes, expected.as_deref()) {
+                            Ok(hash) => Ok((bytes, hash)),
+                            Err(e) => Err((e, Some(bytes))),
+                        },
+                        Err(e) => Err((e.to_string(), None)),
+                    };
+                    if tx.send((name.as_str(), outcome)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+        for (name, outcome) in rx {
+            done(name, outcome)?;
+        }
+        Ok(())
+    })
+}
+
+/// Whether a content type is a web page or API reply, which is what error
+/// responses and login redirects look like, rather than a sound file.
+fn is_page(content_type: &str) -> bool {
+    let kind = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    kind.starts_with("text/") || kind.ends_with("json") || kind.ends_with("xml")
+}
+
+/// Checks a downloaded sound against the manifest's sha256, if it gives
+/// one, and that it decodes. Returns its sha256.
+fn verify(bytes: &[u8], expected: Option<&str>) -> Result<String, String> {
+    let hash = packs::sha256_hex(bytes);
+    if let Some(expected) = expected
+        && !expected.eq_ignore_ascii_case(&hash)
+    {
+        return Err(format!("sha256 is {}, manifest says {}", hash, expected));
+    }
+    player::probe_bytes(bytes.to_vec()).map_err(|e| format!("not a playable sound: {}", e))?;
+    Ok(hash)
+}
+
+/// Loads the local `registry.d` packs and fetches every remote registry,
+/// merging them in that order. A registry that can't be fetched is skipped
+/// with a warning, unless nothing else is available.
+fn fetch_all(cfg: &Config, fetcher: &Fetcher) -> Result<Vec<RegistryPack>, Box<dyn std::error::Error>> {
+    let sources = cfg.registry_sources();
+    let local = load_overlays(&config::registry_dir()?);
+    if sources.is_empty() && local.is_empty() {
+        return Err("no registries configured (set registry_url or add [[registries]])".into());
+    }
+    let mut fetched = Vec::new();
+    if !local.is_empty() {
+        fetched.push((LOCAL_REGISTRY.to_string(), local));
+    }
+    let mut last_error = None;
+    for source in &sources {
+        match fetcher.registry(&source.url) {
+            Ok(packs) => fetched.push((source.name.clone(), packs)),
+            Err(e) => {
+                eprintln!("hookplayer: warning: registry '{}' ({}): {}", source.name, source.url, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    match last_error {
+        Some(e) if fetched.is_empty() => Err(e),
+        _ => Ok(merge_registries(fetched)),
+    }
+}
+
+/// Reads the `*.toml` and `*.json` files in `dir`, each holding a list of
+/// packs shaped like a registry index (`[[packs]]` or `{ "packs": [...] }`).
+/// A relative `source_dir` is relative to the file. Files that can't be
+/// read are skipped with a warning.
+fn load_overlays(dir: &Path) -> Vec<RegistryPack> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "toml" || e == "json"))
+        .collect();
+    files.sort();
+
+    let mut packs = Vec::new();
+    for file in files {
+        let parsed = std::fs::read_to_string(&file).map_err(|e| e.to_string()).and_then(|raw| {
+            if file.extension().is_some_and(|e| e == "json") {
+                serde_json::from_str::<Registry>(&raw).map_err(|e| e.to_string())
+            } else {
+                toml::from_str::<Registry>(&raw).map_err(|e| e.to_string())
+            }
+        });
+        let registry = match parsed {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("hookplayer: warning: skipping {}: {}", file.display(), e);
+                continue;
+            }
+        };
+        for mut pack in registry.packs {
+            if pack.source_dir.is_none() && pack.source_repo.is_empty() {
+                eprintln!(
+                    "hookplayer: warning: {}: pack '{}' needs source_repo or source_dir",
+                    file.display(),
+                    pack.name
+                );
+                continue;
+            }
+            if let Some(src) = &pack.source_dir
+                && !src.starts_with('~')
+                && Path::new(src).is_relative()
+            {
+                pack.source_dir = Some(dir.join(src).display().to_string());
+            }
+            packs.push(pack);
+        }
+    }
+    packs
+}
+
+/// Merges registries in order. The first registry to list a pack name keeps
+/// it; later packs with the same name are namespaced as `registry/name`.
+fn merge_registries(registries: Vec<(String, Vec<RegistryPack>)>) -> Vec<RegistryPack> {
+    let mut merged: Vec<RegistryPack> = Vec::new();
+    for (registry, packs) in registries {
+        for mut pack in packs {
+            pack.id = if merged.iter().any(|p| p.id == pack.name) {
+                format!("{}/{}", registry, pack.name)
+            } else {
+                pack.name.clone()
+            };
+            pack.registry = registry.clone();
+            merged.push(pack);
+        }
+    }
+    merged
+}
+
+/// Finds a pack by id, or by `registry/name` even when the name is unique.
+pub fn find_pack<'a>(packs: &'a [RegistryPack], name: &str) -> Result<&'a RegistryPack, String> {
+    packs
+        .iter()
+        .find(|p| p.id == name)
+        .or_else(|| packs.iter().find(|p| format!("{}/{}", p.registry, p.name) == name))
+        .ok_or_else(|| format!("pack '{}' not found in any registry", name))
+}
+
+pub fn list_packs(cfg: &Config, mode: cache::Mode) -> Result<(), Box<dyn std::error::Error>> {
+    let packs = fetch_all(cfg, &Fetcher::new(cfg, mode)?)?;
     for p in &packs {
-        println!("  {:<28} {}", p.name, p.display_name);
+        println!("  {:<28} {:<32} [{}]", p.id, p.display_name, p.registry);
     }
     println!("\n{} packs available", packs.len());
     Ok(())
 }
 
-pub fn download_pack(pack_name: &str, sounds_dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let packs = fetch_registry()?;
-    let pack = packs
+/// What `hookplayer search` looks for. An empty query matches every pack.
+#[derive(Debug, Default, PartialEq)]
+pub struct Search {
+    pub query: String,
+    /// Only installed packs (`Some(true)`) or only the others (`Some(false)`).
+    pub installed: Option<bool>,
+    /// An openpeon category such as `task.error`, or the hookplayer event
+    /// it maps to.
+    pub category: Option<String>,
+    pub license: Option<String>,
+}
+
+impl Search {
+    /// Scores `pack` against the search, lower is better; None if it is
+    /// filtered out or doesn't match the query.
+    fn score(&self, pack: &RegistryPack, installed: bool) -> Option<u32> {
+        if self.installed.is_some_and(|want| want != installed) {
+            return None;
+        }
+        if let Some(category) = &self.category
+            && !pack.categories.iter().any(|c| c == category || category_to_event(c) == Some(category))
+        {
+            return None;
+        }
+        if let Some(license) = &self.license
+            && !pack.license.as_ref().is_some_and(|l| l.eq_ignore_ascii_case(license))
+        {
+            return None;
+        }
+        if self.query.is_empty() {
+            return Some(0);
+        }
+        [&pack.id, &pack.display_name]
+            .into_iter()
+            .chain(&pack.tags)
+            .filter_map(|text| fuzzy::score(&self.query, text))
+            .min()
+    }
+
+    /// Whether filtering `pack` needs details its index entry leaves out.
+    fn needs_manifest(&self, pack: &RegistryPack) -> bool {
+        (self.category.is_some() && pack.categories.is_empty()) || (self.license.is_some() && pack.license.is_none())
+    }
+}
+
+/// Fills in the categories and license an index entry doesn't list from the
+/// pack's manifest.
+fn add_manifest_details(pack: &mut RegistryPack, manifest: Manifest) {
+    if pack.categories.is_empty() {
+        pack.categories = manifest.categories.into_keys().collect();
+        pack.categories.sort();
+    }
+    if pack.license.is_none() {
+        pack.license = manifest.license;
+    }
+}
+
+pub fn search_packs(cfg: &Config, mode: cache::Mode, search: &Search) -> Result<(), Box<dyn std::error::Error>> {
+    let fetcher = Fetcher::new(cfg, mode)?;
+    let mut packs = fetch_all(cfg, &fetcher)?;
+    let mut unchecked = 0;
+    for pack in packs.iter_mut().filter(|p| search.needs_manifest(p)) {
+        match fetcher.manifest(pack) {
+            Ok(manifest) => add_manifest_details(pack, manifest),
+            Err(_) => unchecked += 1,
+        }
+    }
+    if unchecked > 0 {
+        eprintln!("hookplayer: warning: {} pack(s) left out because their manifest couldn't be read", unchecked);
+    }
+    let sounds_dir = cfg.sounds_dir_abs();
+    let mut found: Vec<(u32, &RegistryPack, bool)> = packs
         .iter()
-        .find(|p| p.name == pack_name)
-        .ok_or_else(|| format!("pack '{}' not found in registry", pack_name))?;
+        .filter_map(|p| {
+            let installed = p.dir_name().is_ok_and(|d| sounds_dir.join(d).is_dir());
+            search.score(p, installed).map(|score| (score, p, installed))
+        })
+        .collect();
+    found.sort_by_key(|(score, _, _)| *score);
 
-    let base_url = pack_base_url(pack);
+    for (_, p, installed) in &found {
+        let mark = if *installed { "  (installed)" } else { "" };
+        println!("  {:<28} {:<32} [{}]{}", p.id, p.display_name, p.registry, mark);
+    }
+    println!("\n{} of {} packs match", found.len(), packs.len());
+    Ok(())
+}
+
+/// Prints where a pack comes from and what its manifest holds.
+pub fn pack_info(cfg: &Config, mode: cache::Mode, pack_name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let fetcher = Fetcher::new(cfg, mode)?;
+    let packs = fetch_all(cfg, &fetcher)?;
+    let pack = find_pack(&packs, pack_name)?;
+    let manifest = fetcher.manifest(pack)?;
+    let install_dir = cfg.sounds_dir_abs().join(pack.dir_name()?);
+
+    println!("{} ({})", pack.display_name, pack.id);
+    if let Some(description) = &pack.description {
+        println!("  {}", description);
+    }
+    println!();
+    println!("  registry:  {}", pack.registry);
+    match &pack.source_dir {
+        Some(dir) => println!("  source:    {}", dir),
+        None => println!("  source:    {} @ {} {}", pack.source_repo, pack.source_ref, pack.source_path),
+    }
+    let license = pack.license.as_ref().or(manifest.license.as_ref());
+    println!("  license:   {}", license.map(String::as_str).unwrap_or("unknown"));
+    if !pack.tags.is_empty() {
+        println!("  tags:      {}", pack.tags.join(", "));
+    }
+    if install_dir.is_dir() {
+        println!("  installed: {}", install_dir.display());
+    } else {
+        println!("  installed: no");
+    }
+
+    println!("\nCategories:");
+    let mut categories: Vec<(&String, &Category)> = manifest.categories.iter().collect();
+    categories.sort_by_key(|(name, _)| name.as_str());
+    let mut total = 0;
+    for (name, category) in categories {
+        let event = category_to_event(name).map(|e| format!("-> {}", e)).unwrap_or_default();
+        println!("  {:<20} {:>3} sound(s)  {}", name, category.sounds.len(), event);
+        total += category.sounds.len();
+    }
+
+    let size = pack.total_size_bytes.or_else(|| {
+        let dir = match &pack.source_dir {
+            Some(dir) => config::expand_tilde(dir).join("sounds"),
+            None => install_dir,
+        };
+        dir_size(&dir)
+    });
+    let size = size.map(progress::describe_size).unwrap_or_else(|| "size unknown".to_string());
+    println!("\n{} sound(s), {}", total, size);
+    Ok(())
+}
+
+fn dir_size(dir: &Path) -> Option<u64> {
+    let entries = std::fs::read_dir(dir).ok()?;
+    Some(entries.filter_map(|e| e.ok()?.metadata().ok()).filter(|m| m.is_file()).map(|m| m.len()).sum())
+}
 
+/// Downloads and installs a pack. With `resume`, files left from an earlier
+/// failed download of the same manifest are reused.
+pub fn download_pack(
+    cfg: &Config,
+    mode: cache::Mode,
+    pack_name: &str,
+    resume: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let fetcher = Fetcher::new(cfg, mode)?;
+    let packs = fetch_all(cfg, &fetcher)?;
+    let pack = find_pack(&packs, pack_name)?;
+
+    let result = install(cfg, &fetcher, pack, resume);
+    update_lock(cfg);
+    result?;
+    println!("Done. Pack '{}' installed.", pack.id);
+    Ok(())
+}
+
+/// Regenerates hookplayer.lock after the installed packs changed.
+pub fn update_lock(cfg: &Config) {
+    if let Err(e) = lock::write(cfg) {
+        eprintln!("hookplayer: warning: could not update lockfile: {}", e);
+    }
+}
+
+/// Downloads a pack's sounds, pinned to the commit its ref points to now,
+/// and writes its receipt. Files the previous receipt shows are already in
+/// place are kept, unless the manifest now gives a different hash or,
+/// lacking hashes, has changed at all.
+///
+/// The pack is assembled in its staging directory and only moved into
+/// place once every file is there. A file that can't be fetched or fails
+/// verification doesn't stop the rest of the pack: it is reported at the
+/// end (and, if it was downloaded, kept in the quarantine dir for
+/// inspection), the installed copy is left as it was, and what did arrive
+/// stays staged for `resume`.
+fn install(
+    cfg: &Config,
+    fetcher: &Fetcher,
+    pack: &RegistryPack,
+    resume: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let commit = fetcher.commit(pack);
+    let source = commit.as_deref().map(|c| pack.at(c)).unwrap_or_else(|| pack.clone());
     println!("Fetching manifest for '{}'...", pack.display_name);
-    let manifest_url = format!("{}/openpeon.json", base_url);
-    let manifest: Manifest = reqwest::blocking::get(&manifest_url)?.json()?;
+    let manifest = fetcher.manifest(&source)?;
+
+    let dir_name = pack.dir_name()?;
+    let out_dir = cfg.sounds_dir_abs().join(&dir_name);
+    let previous = Receipt::load(&out_dir).unwrap_or_default();
+    // Files without a hash in the manifest are only reused from the same commit.
+    let same_manifest = previous.manifest_sha256 == manifest.sha256
+        && previous.source_ref == pack.source_ref
+        && previous.commit == commit;
+    let files = manifest.files();
+
+    let stage = packs::staging_dir(cfg, &dir_name);
+    let partial = Receipt::load(&stage).filter(|r| resume && r.manifest_sha256 == manifest.sha256);
+    if partial.is_none() {
+        let _ = std::fs::remove_dir_all(&stage);
+    }
+    std::fs::create_dir_all(&stage)?;
+
+    println!("Downloading {} sounds into sounds/{}/", files.len(), dir_name);
+
+    let mut receipt = Receipt {
+        pack: pack.id.clone(),
+        registry: pack.registry.clone(),
+        source_repo: pack.source_repo.clone(),
+        source_ref: pack.source_ref.clone(),
+        source_path: pack.source_path.clone(),
+        commit,
+        source_dir: pack.source_dir.clone(),
+        version: manifest.version.clone(),
+        manifest_sha256: manifest.sha256.clone(),
+        installed_at: crate::state::now(),
+        files: BTreeMap::new(),
+    };
+    let quarantine = config::cache_dir().join("quarantine").join(&dir_name);
+    let (mut fetched, mut reused, mut failed) = (0, 0, Vec::new());
+    let mut to_fetch = Vec::new();
+    for (filename, expected) in &files {
+        if let Some(hash) = partial.as_ref().and_then(|p| p.unchanged(&stage, filename)) {
+            receipt.files.insert(filename.clone(), hash.to_string());
+            reused += 1;
+            continue;
+        }
+        let current = previous.unchanged(&out_dir, filename);
+        if let Some(hash) = current
+            && (expected.as_deref() == Some(hash) || (expected.is_none() && same_manifest))
+        {
+            packs::seed(&out_dir.join(filename), &stage.join(filename))?;
+            receipt.files.insert(filename.clone(), hash.to_string());
+            reused += 1;
+            continue;
+        }
+        to_fetch.push((filename.clone(), expected.clone()));
+    }
+    // The stage's receipt lists every verified file as soon as it lands, so
+    // `--resume` can pick up after a crash or an aborted run.
+    receipt.save(&stage)?;
+
+    let mut progress = Progress::new(to_fetch.len());
+    let result = fetch_files(fetcher, &source, &to_fetch, cfg.download_jobs, verify, |filename, outcome| {
+        match outcome {
+            Ok((bytes, hash)) => {
+                fsutil::write_atomic(&stage.join(filename), &bytes)?;
+                receipt.files.insert(filename.to_string(), hash);
+                receipt.save(&stage)?;
+                fetched += 1;
+                progress.file_done(filename, bytes.len() as u64, true);
+            }
+            Err((reason, bytes)) => {
+                if let Some(bytes) = bytes {
+                    let _ = fsutil::write_atomic(&quarantine.join(filename), &bytes);
+                }
+                failed.push((filename.to_string(), reason));
+                progress.file_done(filename, 0, false);
+            }
+        }
+        Ok(())
+    });
+    progress.finish();
+    result?;
+    failed.sort();
+
+    println!("{} fetched, {} reused, {} failed", fetched, reused, failed.len());
+    if failed.is_empty() {
+        for stale in previous.files.keys().filter(|f| !files.contains_key(*f)) {
+            println!("  - {}", stale);
+        }
+        packs::promote(&stage, &out_dir)?;
+        return Ok(());
+    }
+    for (filename, reason) in &failed {
+        println!("  {}: {}", filename, reason);
+    }
+    if quarantine.is_dir() {
+        println!("Rejected downloads were saved in {}", quarantine.display());
+    }
+    Err(format!(
+        "'{}': {} of {} file(s) could not be installed; the installed copy is unchanged \
+         (retry with 'hookplayer download {} --resume')",
+        pack.id,
+        failed.len(),
+        files.len(),
+        pack.id
+    )
+    .into())
+}
+
+/// Why an installed pack differs from the registry's, or None if it
+/// doesn't. `commit` is what the pack's ref points to now, if known.
+fn outdated_reason(
+    receipt: &Receipt,
+    pack: &RegistryPack,
+    commit: Option<&str>,
+    manifest: &Manifest,
+) -> Option<String> {
+    if receipt.manifest_sha256.is_empty() {
+        return Some("installed without a receipt".to_string());
+    }
+    if receipt.source_ref != pack.source_ref {
+        return Some(format!("ref {} -> {}", receipt.source_ref, pack.source_ref));
+    }
+    let missing = manifest.files().keys().filter(|f| !receipt.files.contains_key(*f)).count();
+    if missing > 0 {
+        return Some(format!("{} file(s) missing", missing));
+    }
+    // A moved ref can replace sounds without touching the manifest.
+    if let (Some(old), Some(new)) = (receipt.commit.as_deref(), commit)
+        && old != new
+    {
+        return Some(format!("commit {} -> {}", short(old), short(new)));
+    }
+    if receipt.manifest_sha256 == manifest.sha256 {
+        return None;
+    }
+    match (&receipt.version, &manifest.version) {
+        (Some(old), Some(new)) if old != new => Some(format!("version {} -> {}", old, new)),
+        _ => Some("manifest changed".to_string()),
+    }
+}
+
+fn short(commit: &str) -> &str {
+    commit.get(..7).unwrap_or(commit)
+}
 
-    // Collect unique sound filenames
-    let mut filenames: HashSet<String> = HashSet::new();
-    for category in manifest.categories.values() {
-        for sound in &category.sounds {
-            if let Some(basename) = std::path::Path::new(&sound.file).file_name() {
-                filenames.insert(basename.to_string_lossy().into_owned());
+/// Installed packs with a receipt, paired with the registry entry they
+/// were installed from.
+fn installed_packs<'a>(cfg: &Config, packs: &'a [RegistryPack]) -> Vec<(String, Receipt, Option<&'a RegistryPack>)> {
+    packs::installed(cfg)
+        .into_iter()
+        .filter_map(|dir| {
+            let receipt = Receipt::load(&cfg.sounds_dir_abs().join(&dir))?;
+            let pack = find_pack(packs, &receipt.pack).ok();
+            Some((dir, receipt, pack))
+        })
+        .collect()
+}
+
+/// Lists installed packs whose registry copy has changed since install.
+pub fn outdated(cfg: &Config, mode: cache::Mode) -> Result<(), Box<dyn std::error::Error>> {
+    let fetcher = Fetcher::new(cfg, mode)?;
+    let packs = fetch_all(cfg, &fetcher)?;
+    let mut count = 0;
+    for (dir, receipt, pack) in installed_packs(cfg, &packs) {
+        let Some(pack) = pack else {
+            println!("  {:<28} no longer in any registry", dir);
+            continue;
+        };
+        match fetcher.manifest(pack) {
+            Ok(manifest) => {
+                let commit = fetcher.commit(pack);
+                if let Some(reason) = outdated_reason(&receipt, pack, commit.as_deref(), &manifest) {
+                    println!("  {:<28} {}", pack.id, reason);
+                    count += 1;
+                }
             }
+            Err(e) => eprintln!("hookplayer: warning: {}: {}", pack.id, e),
         }
     }
+    if count == 0 {
+        println!("All packs are up to date.");
+    } else {
+        println!("\n{} pack(s) can be upgraded with 'hookplayer upgrade'", count);
+    }
+    Ok(())
+}
+
+/// Re-installs `pack_name`, or every outdated pack, fetching only the files
+/// that changed.
+pub fn upgrade(cfg: &Config, mode: cache::Mode, pack_name: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let fetcher = Fetcher::new(cfg, mode)?;
+    let packs = fetch_all(cfg, &fetcher)?;
+
+    let mut targets = Vec::new();
+    if let Some(name) = pack_name {
+        let pack = find_pack(&packs, name)?;
+        let receipt = Receipt::load(&cfg.sounds_dir_abs().join(pack.dir_name()?)).unwrap_or_default();
+        targets.push((pack, receipt));
+    } else {
+        for (_, receipt, pack) in installed_packs(cfg, &packs) {
+            targets.extend(pack.map(|p| (p, receipt)));
+        }
+    }
+
+    let (mut upgraded, mut failed) = (0, 0);
+    for (pack, receipt) in targets {
+        let manifest = fetcher.manifest(pack)?;
+        let commit = fetcher.commit(pack);
+        let Some(reason) = outdated_reason(&receipt, pack, commit.as_deref(), &manifest) else {
+            if pack_name.is_some() {
+                println!("'{}' is up to date.", pack.id);
+            }
+            continue;
+        };
+        println!("Upgrading '{}' ({})...", pack.id, reason);
+        match install(cfg, &fetcher, pack, false) {
+            Ok(()) => upgraded += 1,
+            Err(e) => {
+                eprintln!("hookplayer: {}", e);
+                failed += 1;
+            }
+        }
+    }
+    if upgraded + failed > 0 {
+        update_lock(cfg);
+    }
+    if failed > 0 {
+        return Err(format!("{} pack(s) could not be fully upgraded", failed).into());
+    }
+    if pack_name.is_none() {
+        match upgraded {
+            0 => println!("All packs are up to date."),
+            n => println!("Done. {} pack(s) upgraded.", n),
+        }
+    }
+    Ok(())
+}
+
+/// Installs exactly the packs and files pinned in hookplayer.lock. Every
+/// fetched file must match its locked sha256; on a mismatch nothing more
+/// is written and the install fails.
+pub fn install_locked(cfg: &Config, mode: cache::Mode) -> Result<(), Box<dyn std::error::Error>> {
+    let lockfile = lock::load()?;
+    install_lockfile(cfg, &Fetcher::new(cfg, mode)?, &lockfile)
+}
 
-    let out_dir = sounds_dir.join(&pack.name);
-    std::fs::create_dir_all(&out_dir)?;
+fn install_lockfile(cfg: &Config, fetcher: &Fetcher, lockfile: &lock::Lockfile) -> Result<(), Box<dyn std::error::Error>> {
+    let sounds_dir = cfg.sounds_dir_abs();
 
-    println!("Downloading {} sounds into sounds/{}/", filenames.len(), pack.name);
+    for locked in &lockfile.packs {
+        // Namespaced packs are locked by id, e.g. `team/alerts`.
+        let name = locked.id.strip_prefix(&format!("{}/", locked.registry)).unwrap_or(&locked.id);
+        let source = RegistryPack {
+            name: name.to_string(),
+            display_name: locked.id.clone(),
+            source_repo: locked.source_repo.clone(),
+            source_ref: locked.commit.clone().unwrap_or_else(|| locked.source_ref.clone()),
+            source_path: locked.source_path.clone(),
+            source_dir: locked.source_dir.clone(),
+            description: None,
+            tags: Vec::new(),
+            license: None,
+            categories: Vec::new(),
+            total_size_bytes: None,
+            id: locked.id.clone(),
+            registry: locked.registry.clone(),
+        };
+        let dir_name = source.dir_name().map_err(|e| format!("hookplayer.lock: {}", e))?;
+        if let Some(bad) = locked.files.keys().find(|f| packs::check_name(f).is_err()) {
+            return Err(format!("hookplayer.lock: pack '{}' has invalid file name '{}'", locked.id, bad).into());
+        }
+        let out_dir = sounds_dir.join(&dir_name);
+        let previous = Receipt::load(&out_dir).unwrap_or_default();
+        let stage = packs::staging_dir(cfg, &dir_name);
+        let _ = std::fs::remove_dir_all(&stage);
+        std::fs::create_dir_all(&stage)?;
+        println!("Installing '{}' ({} files)...", locked.id, locked.files.len());
 
-    let client = reqwest::blocking::Client::new();
-    for filename in &filenames {
-        let url = format!("{}/sounds/{}", base_url, filename);
-        let dest = out_dir.join(filename);
+        let mut to_fetch = Vec::new();
+        for (filename, expected) in &locked.files {
+            let current = out_dir.join(filename);
+            if std::fs::read(&current).is_ok_and(|b| packs::sha256_hex(&b) == *expected) {
+                packs::seed(&current, &stage.join(filename))?;
+            } else {
+                to_fetch.push((filename.clone(), Some(expected.clone())));
+            }
+        }
 
-        let bytes = client.get(&url).send()?.bytes()?;
-        let mut f = std::fs::File::create(&dest)?;
-        f.write_all(&bytes)?;
-        println!("  + {}", filename);
+        let check = |bytes: &[u8], expected: Option<&str>| {
+            let actual = packs::sha256_hex(bytes);
+            match expected {
+                Some(expected) if actual != expected => {
+                    Err(format!("hash mismatch: lockfile has {}, got {}", expected, actual))
+                }
+                _ => Ok(actual),
+            }
+        };
+        let mut progress = Progress::new(to_fetch.len());
+        let result = fetch_files(fetcher, &source, &to_fetch, cfg.download_jobs, check, |filename, outcome| {
+            let (bytes, _) = outcome.map_err(|(reason, _)| {
+                progress.file_done(filename, 0, false);
+                format!("{}/{}: {}; refusing to install", locked.id, filename, reason)
+            })?;
+            fsutil::write_atomic(&stage.join(filename), &bytes)?;
+            progress.file_done(filename, bytes.len() as u64, true);
+            Ok(())
+        });
+        progress.finish();
+        result?;
+        for stale in previous.files.keys().filter(|f| !locked.files.contains_key(*f)) {
+            println!("  - {}", stale);
+        }
+
+        Receipt {
+            pack: locked.id.clone(),
+            registry: locked.registry.clone(),
+            source_repo: locked.source_repo.clone(),
+            source_ref: locked.source_ref.clone(),
+            source_path: locked.source_path.clone(),
+            commit: locked.commit.clone(),
+            source_dir: locked.source_dir.clone(),
+            files: locked.files.clone(),
+            installed_at: crate::state::now(),
+            ..previous
+        }
+        .save(&stage)?;
+        packs::promote(&stage, &out_dir)?;
     }
 
-    println!("Done. Pack '{}' installed.", pack.name);
+    let locked: Vec<String> = lockfile.packs.iter().map(|p| p.id.replace('/', "-")).collect();
+    for dir in packs::installed(cfg).into_iter().filter(|d| !locked.contains(d)) {
+        eprintln!("hookplayer: warning: '{}' is installed but not in the lockfile", dir);
+    }
+    println!("Done. {} pack(s) match the lockfile.", lockfile.packs.len());
     Ok(())
 }
 
 /// Fetches manifests for the given packs and returns a hookplayer events map
 /// built from their openpeon categories.
 pub fn build_events_for_packs(
+    cfg: &Config,
+    mode: cache::Mode,
     pack_names: &[&str],
 ) -> Result<HashMap<String, Vec<String>>, Box<dyn std::error::Error>> {
-    let packs = fetch_registry()?;
-    let client = reqwest::blocking::Client::builder()
-        .user_agent("hookplayer")
-        .build()?;
+    let fetcher = Fetcher::new(cfg, mode)?;
+    let packs = fetch_all(cfg, &fetcher)?;
 
     let mut events: HashMap<String, Vec<String>> = HashMap::new();
 
     for &pack_name in pack_names {
-        let pack = packs
-            .iter()
-            .find(|p| p.name == pack_name)
-            .ok_or_else(|| format!("pack '{}' not found in registry", pack_name))?;
-
-        let base_url = pack_base_url(pack);
-        let manifest_url = format!("{}/openpeon.json", base_url);
+        let pack = find_pack(&packs, pack_name)?;
+        let dir_name = pack.dir_name()?;
 
         println!("Fetching manifest for '{}'...", pack.display_name);
-        let manifest: Manifest = client.get(&manifest_url).send()?.json()?;
+        let manifest = fetcher.manifest(pack)?;
 
         for (category, cat_data) in &manifest.categories {
             if let Some(event) = category_to_event(category) {
@@ -126,7 +964,7 @@ pub fn build_events_for_packs(
                     if let Some(basename) = std::path::Path::new(&sound.file).file_name() {
                         sounds.push(format!(
                             "{}/{}",
-                            pack_name,
+                            dir_name,
                             basename.to_string_lossy()
                         ));
                     }
@@ -138,6 +976,13 @@ pub fn build_events_for_packs(
     Ok(events)
 }
 
+/// Reads a file from a pack in a local directory.
+fn pack_file(pack: &RegistryPack, rel: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let dir = config::expand_tilde(pack.source_dir.as_deref().unwrap_or_default());
+    let path = dir.join(rel);
+    std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
 fn pack_base_url(pack: &RegistryPack) -> String {
     let path = pack.source_path.trim_matches('/');
     if path.is_empty() || path == "." {
@@ -167,3 +1012,226 @@ fn category_to_event(category: &str) -> Option<&'static str> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack(name: &str) -> RegistryPack {
+        RegistryPack {
+            name: name.into(),
+            display_name: name.into(),
+            source_repo: "org/repo".into(),
+            source_ref: "main".into(),
+            source_path: name.into(),
+            source_dir: None,
+            description: None,
+            tags: Vec::new(),
+            license: None,
+            categories: Vec::new(),
+            total_size_bytes: None,
+            id: String::new(),
+            registry: String::new(),
+        }
+    }
+
+    fn merged() -> Vec<RegistryPack> {
+        merge_registries(vec![
+            ("default".into(), vec![pack("peon"), pack("alerts")]),
+            ("team".into(), vec![pack("alerts"), pack("build")]),
+        ])
+    }
+
+    #[test]
+    fn merge_namespaces_colliding_names() {
+        let packs = merged();
+        let ids: Vec<&str> = packs.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, vec!["peon", "alerts", "team/alerts", "build"]);
+        assert_eq!(packs[2].registry, "team");
+        assert_eq!(packs[2].dir_name().unwrap(), "team-alerts");
+    }
+
+    #[test]
+    fn dir_name_rejects_names_outside_sounds_dir() {
+        for bad in ["..", "../peon", "a/b", ""] {
+            let p = RegistryPack { id: bad.into(), ..pack(bad) };
+            assert!(p.dir_name().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn overlays_read_toml_and_json_and_skip_bad_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("a.toml"),
+            "[[packs]]\nname = \"mine\"\ndisplay_name = \"Mine\"\nsource_dir = \"mine\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("b.json"),
+            r#"{ "packs": [{ "name": "private", "display_name": "Private", "source_repo": "org/sounds" }] }"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("c.toml"), "not = [valid").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "ignored").unwrap();
+
+        let packs = load_overlays(dir.path());
+        let names: Vec<&str> = packs.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["mine", "private"]);
+        assert_eq!(packs[0].source_dir, Some(dir.path().join("mine").display().to_string()));
+        assert_eq!(packs[1].source_ref, "main");
+    }
+
+    #[test]
+    fn pack_file_reads_local_directories() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("sounds")).unwrap();
+        std::fs::write(dir.path().join("sounds/a.wav"), "RIFF").unwrap();
+        let mut p = pack("mine");
+        p.source_dir = Some(dir.path().display().to_string());
+        assert_eq!(pack_file(&p, "sounds/a.wav").unwrap(), b"RIFF");
+        assert!(pack_file(&p, "openpeon.json").is_err());
+    }
+
+    #[test]
+    fn install_lockfile_installs_namespaced_packs() {
+        let source = tempfile::TempDir::new().unwrap();
+        let sounds = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(source.path().join("sounds")).unwrap();
+        std::fs::write(source.path().join("sounds/a.wav"), "one").unwrap();
+        let cfg = Config { sounds_dir: sounds.path().display().to_string(), ..Default::default() };
+        let lockfile = lock::Lockfile {
+            version: 1,
+            packs: vec![lock::LockedPack {
+                id: "team/alerts".into(),
+                registry: "team".into(),
+                source_dir: Some(source.path().display().to_string()),
+                files: [("a.wav".to_string(), packs::sha256_hex(b"one"))].into_iter().collect(),
+                ..Default::default()
+            }],
+        };
+        let fetcher = Fetcher::new(&cfg, cache::Mode::Offline).unwrap();
+        install_lockfile(&cfg, &fetcher, &lockfile).unwrap();
+
+        let dir = sounds.path().join("team-alerts");
+        assert_eq!(std::fs::read(dir.join("a.wav")).unwrap(), b"one");
+        assert_eq!(Receipt::load(&dir).unwrap().pack, "team/alerts");
+    }
+
+    #[test]
+    fn search_filters_then_matches_query() {
+        let mut p = pack("peon");
+        p.id = "peon".into();
+        p.display_name = "Warcraft Peon".into();
+        p.tags = vec!["gaming".into()];
+        p.license = Some("CC-BY-4.0".into());
+        p.categories = vec!["task.error".into(), "task.complete".into()];
+
+        let search = |query: &str| Search { query: query.into(), ..Default::default() };
+        assert_eq!(search("").score(&p, false), Some(0));
+        assert_eq!(search("warcraft").score(&p, false), Some(0));
+        assert_eq!(search("gamnig").score(&p, false), Some(2));
+        assert_eq!(search("alarm").score(&p, false), None);
+
+        assert_eq!(Search { installed: Some(true), ..search("") }.score(&p, false), None);
+        assert_eq!(Search { installed: Some(false), ..search("") }.score(&p, false), Some(0));
+        assert_eq!(Search { category: Some("task.error".into()), ..search("") }.score(&p, false), Some(0));
+        assert_eq!(Search { category: Some("stop".into()), ..search("") }.score(&p, false), Some(0));
+        assert_eq!(Search { category: Some("start".into()), ..search("") }.score(&p, false), None);
+        assert_eq!(Search { license: Some("cc-by-4.0".into()), ..search("") }.score(&p, false), Some(0));
+        assert_eq!(Search { license: Some("MIT".into()), ..search("") }.score(&p, false), None);
+    }
+
+    #[test]
+    fn search_falls_back_to_manifest_categories_and_license() {
+        let mut p = pack("peon");
+        let search = Search { category: Some("stop".into()), license: Some("mit".into()), ..Default::default() };
+        assert!(search.needs_manifest(&p));
+        assert_eq!(search.score(&p, false), None);
+
+        let m = manifest(r#"{ "license": "MIT", "categories": { "task.complete": { "sounds": [] } } }"#);
+        add_manifest_details(&mut p, m);
+        assert!(!search.needs_manifest(&p));
+        assert_eq!(search.score(&p, false), Some(0));
+    }
+
+    fn manifest(raw: &str) -> Manifest {
+        let mut m: Manifest = serde_json::from_str(raw).unwrap();
+        m.sha256 = packs::sha256_hex(raw.as_bytes());
+        m
+    }
+
+    #[test]
+    fn outdated_reason_compares_ref_and_manifest() {
+        let p = pack("peon");
+        let v1 = manifest(r#"{ "version": "1.0", "categories": {} }"#);
+        let v2 = manifest(r#"{ "version": "1.1", "categories": {} }"#);
+        let receipt = Receipt {
+            source_ref: "main".into(),
+            version: v1.version.clone(),
+            manifest_sha256: v1.sha256.clone(),
+            ..Default::default()
+        };
+        assert_eq!(outdated_reason(&receipt, &p, None, &v1), None);
+        assert_eq!(outdated_reason(&receipt, &p, None, &v2), Some("version 1.0 -> 1.1".into()));
+        let pinned = Receipt { commit: Some("0123456789".into()), ..receipt };
+        assert_eq!(outdated_reason(&pinned, &p, Some("0123456789"), &v1), None);
+        assert_eq!(outdated_reason(&pinned, &p, None, &v1), None);
+        assert_eq!(
+            outdated_reason(&pinned, &p, Some("abcdef0123"), &v1),
+            Some("commit 0123456 -> abcdef0".into())
+        );
+        let moved = Receipt { source_ref: "v1".into(), ..pinned };
+        assert_eq!(outdated_reason(&moved, &p, None, &v1), Some("ref v1 -> main".into()));
+        assert!(outdated_reason(&Receipt::default(), &p, None, &v1).is_some());
+    }
+
+    #[test]
+    fn retries_back_off_on_transient_statuses() {
+        assert_eq!(retry_delay(1), Duration::from_millis(500));
+        assert_eq!(retry_delay(2), Duration::from_secs(2));
+        assert!(transient_status(reqwest::StatusCode::BAD_GATEWAY));
+        assert!(transient_status(reqwest::StatusCode::TOO_MANY_REQUESTS));
+        assert!(!transient_status(reqwest::StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn is_page_spots_error_pages() {
+        assert!(is_page("text/html; charset=utf-8"));
+        assert!(is_page("application/json"));
+        assert!(is_page("application/xhtml+xml"));
+        assert!(!is_page("audio/mpeg"));
+        assert!(!is_page("application/octet-stream"));
+    }
+
+    #[test]
+    fn verify_checks_hash_and_decodability() {
+        let wav = player::silent_wav(8);
+        let hash = packs::sha256_hex(&wav);
+        assert_eq!(verify(&wav, None), Ok(hash.clone()));
+        assert_eq!(verify(&wav, Some(&hash.to_uppercase())), Ok(hash));
+        assert!(verify(&wav, Some("00")).unwrap_err().contains("manifest says 00"));
+        assert!(verify(b"<html>Not Found</html>", None).is_err());
+    }
+
+    #[test]
+    fn manifest_files_dedupe_basenames() {
+        let m = manifest(
+            r#"{ "categories": {
+                "task.error": { "sounds": [{ "file": "sounds/a.wav", "sha256": "aa" }] },
+                "task.complete": { "sounds": [{ "file": "sounds/a.wav", "sha256": "aa" }, { "file": "b.wav" }] }
+            } }"#,
+        );
+        let files: Vec<(String, Option<String>)> = m.files().into_iter().collect();
+        assert_eq!(files, vec![("a.wav".into(), Some("aa".into())), ("b.wav".into(), None)]);
+    }
+
+    #[test]
+    fn find_pack_by_id_or_qualified_name() {
+        let packs = merged();
+        assert_eq!(find_pack(&packs, "alerts").unwrap().registry, "default");
+        assert_eq!(find_pack(&packs, "team/alerts").unwrap().registry, "team");
+        assert_eq!(find_pack(&packs, "team/build").unwrap().id, "build");
+        assert!(find_pack(&packs, "nope").is_err());
+    }
+}
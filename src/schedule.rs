@@ -0,0 +1,230 @@
+use crate::config::{Config, ScheduleWindow};
+use chrono::{Datelike, NaiveDateTime, Timelike, Weekday};
+
+/// What the active schedule windows do to playback right now.
+#[derive(Debug, Default, PartialEq)]
+pub struct Effect {
+    pub mute: bool,
+    /// The lowest volume cap among active windows.
+    pub volume: Option<f32>,
+    /// The profile of the first active window (by name) that sets one.
+    pub profile: Option<String>,
+    /// Names of the active windows, sorted.
+    pub windows: Vec<String>,
+}
+
+pub fn now() -> NaiveDateTime {
+    chrono::Local::now().naive_local()
+}
+
+/// Combines every window active at `now` into a single effect.
+pub fn effect(cfg: &Config, now: NaiveDateTime) -> Effect {
+    let mut names: Vec<&String> = cfg.schedule.keys().collect();
+    names.sort();
+
+    let mut effect = Effect::default();
+    for name in names {
+        let window = &cfg.schedule[name];
+        if !is_active(window, now) {
+            continue;
+        }
+        effect.mute |= window.mute;
+        if let Some(v) = window.volume {
+            effect.volume = Some(effect.volume.map_or(v, |cur| cur.min(v)));
+        }
+        if effect.profile.is_none() {
+            effect.profile = window.profile.clone();
+        }
+        effect.windows.push(name.clone());
+    }
+    effect
+}
+
+/// Applies the schedule to `cfg` by switching to the window's profile.
+/// Returns the effect so the caller can honour `mute` and the volume cap.
+pub fn apply(cfg: &mut Config, now: NaiveDateTime) -> Result<Effect, String> {
+    let effect = effect(cfg, now);
+    if let Some(profile) = &effect.profile {
+        cfg.apply_profile(profile)?;
+    }
+    Ok(effect)
+}
+
+/// Prints each window, whether it is active now, and the combined effect.
+pub fn status(cfg: &Config) {
+    let now = now();
+    println!("Now: {}", now.format("%a %H:%M"));
+
+    if cfg.schedule.is_empty() {
+        println!("No schedule windows configured.");
+        return;
+    }
+
+    let mut names: Vec<&String> = cfg.schedule.keys().collect();
+    names.sort();
+    for name in names {
+        let w = &cfg.schedule[name];
+        let days = if w.days.is_empty() { "every day".to_string() } else { w.days.join(",") };
+        let mut actions = Vec::new();
+        if w.mute {
+            actions.push("mute".to_string());
+        }
+        if let Some(v) = w.volume {
+            actions.push(format!("volume {}", v));
+        }
+        if let Some(p) = &w.profile {
+            actions.push(format!("profile {}", p));
+        }
+        let state = if is_active(w, now) { "active" } else { "inactive" };
+        println!(
+            "  {:<16} {}-{} {:<20} {:<24} {}",
+            name, w.from, w.to, days, actions.join(", "), state
+        );
+    }
+
+    let e = effect(cfg, now);
+    if e.windows.is_empty() {
+        println!("\nNo window is active; playing normally.");
+    } else if e.mute {
+        println!("\nMuted by: {}", e.windows.join(", "));
+    } else {
+        let mut parts = Vec::new();
+        if let Some(v) = e.volume {
+            parts.push(format!("volume capped at {}", v));
+        }
+        if let Some(p) = &e.profile {
+            parts.push(format!("profile '{}'", p));
+        }
+        println!("\nActive: {} ({})", e.windows.join(", "), parts.join(", "));
+    }
+}
+
+/// Checks a window's days and times.
+pub fn validate_window(w: &ScheduleWindow) -> Result<(), String> {
+    parse_time(&w.from)?;
+    parse_time(&w.to)?;
+    for day in &w.days {
+        parse_day(day)?;
+    }
+    if !w.mute && w.volume.is_none() && w.profile.is_none() {
+        return Err("window has no effect (set mute, volume or profile)".into());
+    }
+    Ok(())
+}
+
+/// Returns true if `now` falls inside the window. Windows that cross
+/// midnight are matched against the weekday they started on.
+pub fn is_active(w: &ScheduleWindow, now: NaiveDateTime) -> bool {
+    let (Ok(from), Ok(to)) = (parse_time(&w.from), parse_time(&w.to)) else {
+        return false;
+    };
+    let minute = now.hour() * 60 + now.minute();
+    let today = now.weekday();
+    let on = |day: Weekday| w.days.is_empty() || w.days.iter().any(|d| parse_day(d) == Ok(day));
+
+    if from == to {
+        on(today)
+    } else if from < to {
+        on(today) && minute >= from && minute < to
+    } else {
+        (on(today) && minute >= from) || (on(today.pred()) && minute < to)
+    }
+}
+
+/// Parses `HH:MM` into minutes since midnight.
+fn parse_time(s: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid time '{}' (expected HH:MM)", s);
+    let (h, m) = s.split_once(':').ok_or_else(invalid)?;
+    let h: u32 = h.parse().map_err(|_| invalid())?;
+    let m: u32 = m.parse().map_err(|_| invalid())?;
+    if h > 23 || m > 59 {
+        return Err(invalid());
+    }
+    Ok(h * 60 + m)
+}
+
+fn parse_day(s: &str) -> Result<Weekday, String> {
+    s.parse::<Weekday>()
+        .map_err(|_| format!("invalid day '{}' (expected mon, tue, ... sun)", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn window(days: &[&str], from: &str, to: &str) -> ScheduleWindow {
+        ScheduleWindow {
+            days: days.iter().map(|d| d.to_string()).collect(),
+            from: from.into(),
+            to: to.into(),
+            mute: true,
+            volume: None,
+            profile: None,
+        }
+    }
+
+    // 2026-10-16 is a Friday.
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 10, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn same_day_window() {
+        let w = window(&[], "09:00", "17:30");
+        assert!(is_active(&w, at(16, 9, 0)));
+        assert!(is_active(&w, at(16, 17, 29)));
+        assert!(!is_active(&w, at(16, 17, 30)));
+        assert!(!is_active(&w, at(16, 8, 59)));
+    }
+
+    #[test]
+    fn overnight_window_belongs_to_start_day() {
+        let w = window(&["fri"], "22:00", "07:00");
+        assert!(is_active(&w, at(16, 23, 0))); // Fri night
+        assert!(is_active(&w, at(17, 6, 59))); // early Sat, started Fri
+        assert!(!is_active(&w, at(16, 6, 0))); // early Fri, started Thu
+        assert!(!is_active(&w, at(17, 22, 30))); // Sat night
+    }
+
+    #[test]
+    fn days_accept_short_and_long_names() {
+        let w = window(&["Saturday", "sun"], "00:00", "00:00");
+        assert!(is_active(&w, at(17, 12, 0)));
+        assert!(is_active(&w, at(18, 12, 0)));
+        assert!(!is_active(&w, at(16, 12, 0)));
+    }
+
+    #[test]
+    fn validate_rejects_bad_times_and_days() {
+        assert!(validate_window(&window(&[], "7:00", "24:00")).is_err());
+        assert!(validate_window(&window(&["funday"], "07:00", "08:00")).is_err());
+        assert!(validate_window(&window(&["mon"], "07:00", "08:00")).is_ok());
+    }
+
+    #[test]
+    fn effect_combines_active_windows() {
+        let mut cfg = Config::default();
+        let mut quiet = window(&[], "20:00", "23:00");
+        quiet.mute = false;
+        quiet.volume = Some(0.3);
+        quiet.profile = Some("night".into());
+        let mut quieter = window(&[], "21:00", "22:00");
+        quieter.mute = false;
+        quieter.volume = Some(0.1);
+        cfg.schedule.insert("b-quiet".into(), quiet);
+        cfg.schedule.insert("a-quieter".into(), quieter);
+
+        let e = effect(&cfg, at(16, 21, 30));
+        assert_eq!(
+            e,
+            Effect {
+                mute: false,
+                volume: Some(0.1),
+                profile: Some("night".into()),
+                windows: vec!["a-quieter".into(), "b-quiet".into()],
+            }
+        );
+        assert_eq!(effect(&cfg, at(16, 12, 0)), Effect::default());
+    }
+}
@@ -0,0 +1,402 @@
+use crate::config::{self, Config, ConfigError};
+use crate::sounds;
+use std::path::{Path, PathBuf};
+
+/// A per-project overlay, found by walking up from the working directory.
+pub const PROJECT_FILE: &str = ".hookplayer.toml";
+
+/// Environment variables and the config keys they override. When two
+/// variables set the same key, the later one here wins.
+const ENV_VARS: &[(&str, &str)] = &[
+    ("HOOKPLAYER_SOUNDS_DIR", "sounds_dir"),
+    ("HOOKPLAYER_VOLUME", "volume"),
+    ("HOOKPLAYER_MUTE", "mute"),
+    ("HOOKPLAYER_PROFILE", "profile"),
+    ("HOOKPLAYER_DEVICE", "device"),
+    ("HOOKPLAYER_BACKEND", "backend"),
+    ("HOOKPLAYER_REGISTRY_URL", "registry_url"),
+    ("GITHUB_TOKEN", "github_token"),
+    ("HOOKPLAYER_GITHUB_TOKEN", "github_token"),
+];
+
+/// Command-line flags that take a value, and the config keys they override.
+/// `--mute` is handled on its own as it takes none.
+const FLAGS: &[(&str, &str)] = &[
+    ("--sounds-dir", "sounds_dir"),
+    ("--volume", "volume"),
+    ("--profile", "profile"),
+    ("--device", "device"),
+    ("--backend", "backend"),
+    ("--registry-url", "registry_url"),
+];
+
+/// Loads the effective settings. Each layer overrides the one before it:
+///
+/// 1. built-in defaults
+/// 2. `~/.config/hookplayer/config.toml`
+/// 3. the nearest `.hookplayer.toml` in the working directory or above
+/// 4. `HOOKPLAYER_*` environment variables
+/// 5. command-line flags (`cli`, from `take_flags`)
+///
+/// Tables such as `[events]` merge key by key, so an overlay only needs the
+/// entries it changes. Also returns a warning for each ignored key.
+pub fn load(cli: &toml::Table) -> Result<(Config, Vec<String>), ConfigError> {
+    let path = config::config_path().map_err(|e| ConfigError {
+        path: PathBuf::from("config.toml"),
+        message: e.to_string(),
+        location: None,
+        snippet: None,
+    })?;
+
+    let mut merged = toml::Table::new();
+    let mut warnings = Vec::new();
+    if path.is_file() {
+        let raw = config::read_config(&path)?;
+        merge(&mut merged, load_file(&path, &raw, false, &mut Vec::new(), &mut warnings)?);
+    }
+
+    let cwd = std::env::current_dir().unwrap_or_default();
+    if let Some(project) = find_project_file(&cwd)
+        && let Some(raw) = read_layer(&project)?
+    {
+        merge(&mut merged, load_file(&project, &raw, true, &mut Vec::new(), &mut warnings)?);
+    }
+
+    merge(&mut merged, env_layer(std::env::vars())?);
+    merge(&mut merged, cli.clone());
+
+    Ok((config::from_table(&path, merged)?, warnings))
+}
+
+/// Parses a config file and everything it includes into one table. Includes
+/// apply first, in the order listed (glob matches sorted by name), and the
+/// file's own settings go on top; see `merge` for how they combine. An
+/// included file's relative `sounds_dir` is relative to that file, as is a
+/// project file's (`rebase`). `stack` holds the files being loaded, to
+/// catch include cycles.
+fn load_file(
+    path: &Path,
+    raw: &str,
+    rebase: bool,
+    stack: &mut Vec<PathBuf>,
+    warnings: &mut Vec<String>,
+) -> Result<toml::Table, ConfigError> {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if let Some(pos) = stack.iter().position(|p| *p == canonical) {
+        let mut cycle: Vec<String> = stack[pos..].iter().map(|p| p.display().to_string()).collect();
+        cycle.push(canonical.display().to_string());
+        let includer = stack[stack.len() - 1].display().to_string();
+        return Err(layer_error(&includer, format!("include cycle: {}", cycle.join(" -> "))));
+    }
+
+    // Files other than config.toml are upgraded in memory only.
+    let raw = match config::migrate(path, raw, config::MIGRATIONS)? {
+        Some(migration) => migration.output,
+        None => raw.to_string(),
+    };
+    let (mut table, w) = config::parse_table(path, &raw)?;
+    if stack.is_empty() && !rebase {
+        warnings.extend(w);
+    } else {
+        warnings.extend(w.into_iter().map(|w| format!("{}: {}", path.display(), w)));
+    }
+    let dir = path.parent().unwrap_or(Path::new("."));
+    if rebase {
+        rebase_sounds_dir(&mut table, dir);
+    }
+
+    let includes = match table.remove("include") {
+        Some(toml::Value::Array(entries)) => entries,
+        _ => Vec::new(),
+    };
+    stack.push(canonical);
+    let mut merged = toml::Table::new();
+    for entry in includes.iter().filter_map(|e| e.as_str()) {
+        for file in resolve_include(dir, entry).map_err(|e| layer_error(&path.display().to_string(), e))? {
+            let raw = std::fs::read_to_string(&file)
+                .map_err(|e| layer_error(&file.display().to_string(), e.to_string()))?;
+            merge(&mut merged, load_file(&file, &raw, true, stack, warnings)?);
+        }
+    }
+    stack.pop();
+
+    merge(&mut merged, table);
+    Ok(merged)
+}
+
+/// Turns an `include` entry into the files it names. Globs may match
+/// nothing; a plain path must exist.
+fn resolve_include(dir: &Path, entry: &str) -> Result<Vec<PathBuf>, String> {
+    let (root, rest) = sounds::locate(dir, entry);
+    if sounds::has_wildcard(&rest) {
+        return Ok(sounds::glob(&root, &rest, &["toml"]).into_iter().map(|p| root.join(p)).collect());
+    }
+    let file = root.join(rest);
+    if !file.is_file() {
+        return Err(format!("include '{}': {} not found", entry, file.display()));
+    }
+    Ok(vec![file])
+}
+
+/// Removes the settings flags from `args` and returns them as a layer.
+/// Flags may appear anywhere, as `--flag value` or `--flag=value`.
+pub fn take_flags(args: &mut Vec<String>) -> Result<toml::Table, ConfigError> {
+    let mut pairs = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        if args[i] == "--mute" {
+            args.remove(i);
+            pairs.push(("--mute", "mute", toml::Value::Boolean(true)));
+            continue;
+        }
+        let Some(&(flag, key)) = FLAGS.iter().find(|(flag, _)| {
+            args[i] == *flag || args[i].strip_prefix(flag).is_some_and(|rest| rest.starts_with('='))
+        }) else {
+            i += 1;
+            continue;
+        };
+        let arg = args.remove(i);
+        let value = match arg.split_once('=') {
+            Some((_, value)) => value.to_string(),
+            None if i < args.len() => args.remove(i),
+            None => return Err(layer_error("command line", format!("{} needs a value", flag))),
+        };
+        pairs.push((flag, key, toml::Value::String(value)));
+    }
+    check_layer("command line", pairs)
+}
+
+/// Builds the environment layer from `HOOKPLAYER_*` variables.
+fn env_layer(vars: impl Iterator<Item = (String, String)>) -> Result<toml::Table, ConfigError> {
+    let vars: std::collections::HashMap<String, String> = vars.collect();
+    let mut pairs = Vec::new();
+    for &(var, key) in ENV_VARS {
+        let Some(value) = vars.get(var).cloned() else {
+            continue;
+        };
+        let value = if key == "mute" {
+            toml::Value::Boolean(parse_bool(&value).ok_or_else(|| {
+                layer_error("environment", format!("{}: expected true or false, got '{}'", var, value))
+            })?)
+        } else {
+            toml::Value::String(value)
+        };
+        pairs.push((var, key, value));
+    }
+    check_layer("environment", pairs)
+}
+
+/// Checks each override on its own so an error names the variable or flag
+/// that caused it, then collects them into one table.
+fn check_layer(source: &str, pairs: Vec<(&str, &str, toml::Value)>) -> Result<toml::Table, ConfigError> {
+    let mut layer = toml::Table::new();
+    for (name, key, value) in pairs {
+        let single = toml::Table::from_iter([(key.to_string(), value.clone())]);
+        toml::Value::Table(single)
+            .try_into::<Config>()
+            .map_err(|e| layer_error(source, format!("{}: {}", name, e.message())))?;
+        layer.insert(key.to_string(), value);
+    }
+    Ok(layer)
+}
+
+fn layer_error(source: &str, message: String) -> ConfigError {
+    ConfigError { path: PathBuf::from(source), message, location: None, snippet: None }
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// Reads a layer file, treating a missing file as an empty layer.
+fn read_layer(path: &Path) -> Result<Option<String>, ConfigError> {
+    match std::fs::read_to_string(path) {
+        Ok(raw) => Ok(Some(raw)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(ConfigError {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+            location: None,
+            snippet: None,
+        }),
+    }
+}
+
+pub fn find_project_file(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|p| p.is_file())
+}
+
+/// A relative `sounds_dir` in a project file is relative to that file, so a
+/// repo can ship its own sounds.
+fn rebase_sounds_dir(table: &mut toml::Table, dir: &Path) {
+    if let Some(toml::Value::String(s)) = table.get_mut("sounds_dir")
+        && !s.starts_with('~')
+        && Path::new(s.as_str()).is_relative()
+    {
+        *s = dir.join(&*s).display().to_string();
+    }
+}
+
+/// Merges `overlay` into `base`. Tables merge recursively; any other value
+/// replaces what was there. So `volume` and `sounds_dir` come from the last
+/// layer that sets them, and `[events]` merges per event, with each event's
+/// list taken whole from the last layer that lists it.
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(b)), toml::Value::Table(o)) => merge(b, o),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(raw: &str) -> toml::Table {
+        toml::from_str(raw).unwrap()
+    }
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn merge_overrides_scalars_and_merges_tables() {
+        let mut base = table("volume = 0.5\n[events]\nstart = [\"a.mp3\"]\nstop = [\"b.mp3\"]\n");
+        merge(&mut base, table("volume = 0.2\n[events]\nstop = [\"c.mp3\"]\n"));
+        assert_eq!(
+            base,
+            table("volume = 0.2\n[events]\nstart = [\"a.mp3\"]\nstop = [\"c.mp3\"]\n")
+        );
+    }
+
+    #[test]
+    fn env_layer_maps_known_variables() {
+        let vars = [
+            ("HOOKPLAYER_SOUNDS_DIR", "/override/sounds"),
+            ("HOOKPLAYER_VOLUME", "-6dB"),
+            ("HOOKPLAYER_MUTE", "yes"),
+            ("HOOKPLAYER_OTHER", "ignored"),
+            ("PATH", "/bin"),
+        ];
+        let layer = env_layer(vars.iter().map(|(k, v)| (k.to_string(), v.to_string()))).unwrap();
+        assert_eq!(
+            layer,
+            table("sounds_dir = \"/override/sounds\"\nvolume = \"-6dB\"\nmute = true\n")
+        );
+    }
+
+    #[test]
+    fn env_layer_names_the_bad_variable() {
+        let vars = [("HOOKPLAYER_VOLUME".to_string(), "loud".to_string())];
+        let err = env_layer(vars.into_iter()).unwrap_err();
+        assert!(err.to_string().starts_with("environment: HOOKPLAYER_VOLUME: invalid volume"), "{}", err);
+
+        let vars = [("HOOKPLAYER_MUTE".to_string(), "maybe".to_string())];
+        assert!(env_layer(vars.into_iter()).is_err());
+    }
+
+    #[test]
+    fn take_flags_strips_settings_from_args() {
+        let mut list = args(&["hookplayer", "--volume", "0.2", "stop", "--mute", "--device=USB Audio"]);
+        let layer = take_flags(&mut list).unwrap();
+        assert_eq!(list, args(&["hookplayer", "stop"]));
+        assert_eq!(layer, table("volume = \"0.2\"\nmute = true\ndevice = \"USB Audio\"\n"));
+    }
+
+    #[test]
+    fn take_flags_rejects_missing_value() {
+        let mut list = args(&["hookplayer", "stop", "--profile"]);
+        assert!(take_flags(&mut list).is_err());
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let mut merged = table("volume = 0.5\nsounds_dir = \"~/s\"\n");
+        merge(&mut merged, table("volume = 0.3\n"));
+        merge(&mut merged, env_layer([("HOOKPLAYER_VOLUME".into(), "20%".into())].into_iter()).unwrap());
+        let cfg = config::from_table(Path::new("config.toml"), merged).unwrap();
+        assert_eq!(cfg.volume, 0.2);
+        assert_eq!(cfg.sounds_dir, "~/s");
+    }
+
+    #[test]
+    fn project_sounds_dir_is_relative_to_the_file() {
+        let mut layer = table("sounds_dir = \"sounds\"\n");
+        rebase_sounds_dir(&mut layer, Path::new("/repo"));
+        assert_eq!(layer["sounds_dir"].as_str(), Some("/repo/sounds"));
+
+        let mut layer = table("sounds_dir = \"~/s\"\n");
+        rebase_sounds_dir(&mut layer, Path::new("/repo"));
+        assert_eq!(layer["sounds_dir"].as_str(), Some("~/s"));
+    }
+
+    fn load(path: &Path) -> Result<toml::Table, ConfigError> {
+        let raw = std::fs::read_to_string(path).unwrap();
+        load_file(path, &raw, false, &mut Vec::new(), &mut Vec::new())
+    }
+
+    #[test]
+    fn includes_apply_in_order_under_the_including_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let d = dir.path();
+        std::fs::create_dir_all(d.join("conf.d")).unwrap();
+        std::fs::write(d.join("team.toml"), "volume = 0.8\nsounds_dir = \"team-sounds\"\n[events]\nstart = [\"team/hi.mp3\"]\nstop = [\"team/bye.mp3\"]\n").unwrap();
+        std::fs::write(d.join("conf.d/10-quiet.toml"), "volume = 0.3\n").unwrap();
+        std::fs::write(d.join("conf.d/20-stop.toml"), "[events]\nstop = [\"mine/bye.mp3\"]\n").unwrap();
+        std::fs::write(
+            d.join("config.toml"),
+            "include = [\"team.toml\", \"conf.d/*.toml\"]\nvolume = 0.5\n[events]\nnotify = [\"mine/ping.mp3\"]\n",
+        )
+        .unwrap();
+
+        let cfg = config::from_table(Path::new("config.toml"), load(&d.join("config.toml")).unwrap()).unwrap();
+        assert_eq!(cfg.volume, 0.5);
+        assert_eq!(cfg.sounds_dir, d.join("team-sounds").display().to_string());
+        assert_eq!(cfg.events["start"], vec!["team/hi.mp3".to_string()]);
+        assert_eq!(cfg.events["stop"], vec!["mine/bye.mp3".to_string()]);
+        assert_eq!(cfg.events["notify"], vec!["mine/ping.mp3".to_string()]);
+        assert!(cfg.include.is_empty());
+    }
+
+    #[test]
+    fn include_cycles_are_reported() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("a.toml"), "include = [\"b.toml\"]\n").unwrap();
+        std::fs::write(dir.path().join("b.toml"), "include = [\"a.toml\"]\n").unwrap();
+        let err = load(&dir.path().join("a.toml")).unwrap_err();
+        assert!(err.message.starts_with("include cycle: "), "{}", err);
+        assert!(err.message.ends_with("a.toml"), "{}", err);
+    }
+
+    #[test]
+    fn missing_include_is_an_error_but_empty_glob_is_not() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(dir.path().join("config.toml"), "include = [\"conf.d/*.toml\"]\n").unwrap();
+        assert!(load(&dir.path().join("config.toml")).is_ok());
+
+        std::fs::write(dir.path().join("config.toml"), "include = [\"team.toml\"]\n").unwrap();
+        let err = load(&dir.path().join("config.toml")).unwrap_err();
+        assert!(err.message.contains("include 'team.toml'"), "{}", err);
+    }
+
+    #[test]
+    fn find_project_file_walks_up() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let nested = dir.path().join("a/b");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(dir.path().join(PROJECT_FILE), "").unwrap();
+        assert_eq!(find_project_file(&nested), Some(dir.path().join(PROJECT_FILE)));
+    }
+}
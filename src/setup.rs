@@ -1,8 +1,11 @@
+use crate::config;
+use crate::fsutil;
 use serde_json::{json, Value};
 use std::io::{self, Write};
 use std::path::PathBuf;
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod tests {
     use super::*;
     use std::fs;
@@ -184,6 +187,20 @@ mod tests {
         assert_eq!(stop.len(), 1);
     }
 
+    #[test]
+    fn refuses_to_overwrite_invalid_json() {
+        let (dir, path) = temp_settings(None);
+        fs::write(&path, "{ not json").unwrap();
+        let orig_home = std::env::var("HOME").unwrap_or_default();
+        unsafe { std::env::set_var("HOME", dir.path()) };
+
+        let result = run(true);
+
+        unsafe { std::env::set_var("HOME", orig_home) };
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{ not json");
+    }
+
     #[test]
     fn yes_writes_valid_json() {
         let (dir, path) = temp_settings(None);
@@ -210,7 +227,7 @@ const HOOKS: &[Hook] = &[
     Hook { event: "Stop",         command: "hookplayer stop"       },
 ];
 
-fn settings_path() -> PathBuf {
+pub fn settings_path() -> PathBuf {
     let home = std::env::var("HOME").unwrap_or_else(|_| ".".into());
     PathBuf::from(home).join(".claude/settings.json")
 }
@@ -242,10 +259,15 @@ fn has_hookplayer_hook(settings: &Value, event: &str) -> bool {
 
 pub fn run(yes: bool) -> Result<(), Box<dyn std::error::Error>> {
     let path = settings_path();
+    let backups = config::backups_dir()?;
+    // Held until we've written, so two setups can't interleave.
+    let _lock = fsutil::lock(&path, &config::locks_dir()?)?;
 
     let mut settings: Value = if path.exists() {
         let raw = std::fs::read_to_string(&path)?;
-        serde_json::from_str(&raw).unwrap_or(json!({}))
+        serde_json::from_str(&raw).map_err(|e| {
+            format!("{} is not valid JSON ({}); fix or move it before running setup", path.display(), e)
+        })?
     } else {
         json!({})
     };
@@ -303,10 +325,10 @@ pub fn run(yes: bool) -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)?;
+    if let Some(saved) = fsutil::backup(&path, &backups)? {
+        println!("Backed up {} to {}", path.display(), saved.display());
     }
-    std::fs::write(&path, serde_json::to_string_pretty(&settings)? + "\n")?;
+    fsutil::write_atomic(&path, (serde_json::to_string_pretty(&settings)? + "\n").as_bytes())?;
 
     println!("\nDone. Claude Code will play sounds for hook events.");
     Ok(())
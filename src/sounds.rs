@@ -0,0 +1,401 @@
+use crate::config::{self, SoundDef};
+use crate::{fsutil, player};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// File extensions picked up by directory and glob entries.
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "wav", "ogg", "flac"];
+
+/// How long an event waits for a URL sound before giving up on it.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A sound file ready to play, with any options from the `[sounds]` library.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clip {
+    pub path: PathBuf,
+    pub gain: f32,
+    pub start: Option<f32>,
+    pub end: Option<f32>,
+    /// Where to fetch `path` from if it is not in the cache yet.
+    pub url: Option<String>,
+}
+
+impl Clip {
+    pub fn new(path: PathBuf) -> Self {
+        Clip { path, gain: 1.0, start: None, end: None, url: None }
+    }
+}
+
+/// Expands an event's sound list into clips.
+///
+/// Entries are paths relative to `base` (`pack/a.mp3`), absolute or
+/// `~/` paths, `http(s)://` URLs, globs (`pack/*.mp3`), directories
+/// (`pack/`), library references (`@ding`) or exclusions
+/// (`!pack/annoying*.mp3`). Globs and directories expand to the audio files
+/// they match in sorted order; exclusions remove matches from the whole list.
+/// URLs map to a file in the cache dir, see `fetch`. The result keeps entry
+/// order and has no duplicates, so it is stable from one invocation to the next.
+pub fn expand(base: &Path, entries: &[String], library: &HashMap<String, SoundDef>) -> Vec<Clip> {
+    let excludes: Vec<&str> = entries
+        .iter()
+        .filter_map(|e| e.strip_prefix('!'))
+        .map(|x| x.strip_prefix('@').and_then(|a| library.get(a)).map_or(x, |d| d.file.as_str()))
+        .collect();
+
+    let mut out: Vec<Clip> = Vec::new();
+    for entry in entries.iter().filter(|e| !e.starts_with('!')) {
+        let (file, def) = match entry.strip_prefix('@') {
+            Some(alias) => match library.get(alias) {
+                Some(def) => (def.file.as_str(), Some(def)),
+                None => continue,
+            },
+            None => (entry.as_str(), None),
+        };
+        let url = is_url(file).then(|| file.to_string());
+        for path in expand_entry(base, file) {
+            let excluded = excludes.iter().any(|x| is_excluded(base, x, &path));
+            if excluded || out.iter().any(|c| c.path == path) {
+                continue;
+            }
+            let mut clip = match def {
+                Some(d) => Clip { path, gain: d.gain, start: d.start, end: d.end, url: None },
+                None => Clip::new(path),
+            };
+            clip.url = url.clone();
+            out.push(clip);
+        }
+    }
+    out
+}
+
+/// Checks that an entry (or a `[sounds]` file) is a form `expand` understands.
+pub fn validate_entry(entry: &str) -> Result<(), String> {
+    let entry = entry.trim_start_matches('!');
+    if let Some((scheme, rest)) = entry.split_once("://") {
+        if scheme != "http" && scheme != "https" {
+            return Err(format!("'{}': only http:// and https:// URLs are supported", entry));
+        }
+        let host = rest.split('/').next().unwrap_or_default();
+        if host.is_empty() {
+            return Err(format!("'{}': URL has no host", entry));
+        }
+        if has_wildcard(rest.split('?').next().unwrap_or_default()) || entry.ends_with('/') {
+            return Err(format!("'{}': URLs must name a single file", entry));
+        }
+    } else if entry.starts_with('~') && !entry.starts_with("~/") {
+        return Err(format!("'{}': only '~/' is expanded in paths", entry));
+    }
+    Ok(())
+}
+
+/// Downloads a clip's URL into the cache, unless it is already there. Only
+/// audio that decodes is cached, so an error page is never played or kept.
+pub fn fetch(clip: &Clip) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(url) = &clip.url else {
+        return Ok(());
+    };
+    if clip.path.is_file() {
+        return Ok(());
+    }
+    let client = reqwest::blocking::Client::builder().user_agent("hookplayer").timeout(FETCH_TIMEOUT).build()?;
+    let bytes = client.get(url).send()?.error_for_status()?.bytes()?.to_vec();
+    player::probe_bytes(bytes.clone()).map_err(|e| format!("not a playable sound: {}", e))?;
+    fsutil::write_atomic(&clip.path, &bytes)?;
+    Ok(())
+}
+
+fn is_url(entry: &str) -> bool {
+    entry.starts_with("http://") || entry.starts_with("https://")
+}
+
+/// The cache file a URL is downloaded to: a hash of the URL, keeping the
+/// extension so the decoder can tell the format.
+fn url_cache_path(url: &str) -> PathBuf {
+    let digest = Sha256::digest(url.as_bytes());
+    let name: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let file = match Path::new(path).extension() {
+        Some(ext) => format!("{}.{}", name, ext.to_string_lossy()),
+        None => name,
+    };
+    config::cache_dir().join("sounds").join(file)
+}
+
+/// Splits an entry into the directory it is relative to and the rest.
+pub fn locate(base: &Path, entry: &str) -> (PathBuf, String) {
+    if let Some(rest) = entry.strip_prefix("~/") {
+        (config::expand_tilde("~/"), rest.to_string())
+    } else if let Some(rest) = entry.strip_prefix('/') {
+        (PathBuf::from("/"), rest.to_string())
+    } else {
+        (base.to_path_buf(), entry.to_string())
+    }
+}
+
+/// Expands a single include entry into paths. Plain entries are returned
+/// as-is whether or not the file exists.
+fn expand_entry(base: &Path, entry: &str) -> Vec<PathBuf> {
+    if is_url(entry) {
+        return vec![url_cache_path(entry)];
+    }
+    let (root, entry) = locate(base, entry);
+    let matches = if let Some(dir) = entry.strip_suffix('/') {
+        let pattern = if dir.is_empty() { "*".to_string() } else { format!("{}/*", dir) };
+        glob(&root, &pattern, AUDIO_EXTENSIONS)
+    } else if has_wildcard(&entry) {
+        glob(&root, &entry, AUDIO_EXTENSIONS)
+    } else {
+        vec![entry]
+    };
+    matches.into_iter().map(|rel| root.join(rel)).collect()
+}
+
+fn is_excluded(base: &Path, exclude: &str, path: &Path) -> bool {
+    if is_url(exclude) {
+        return url_cache_path(exclude) == path;
+    }
+    let (root, pattern) = locate(base, exclude);
+    match path.strip_prefix(&root) {
+        Ok(rel) => matches_path(&pattern, &rel.to_string_lossy()),
+        Err(_) => false,
+    }
+}
+
+pub fn has_wildcard(s: &str) -> bool {
+    s.contains(['*', '?'])
+}
+
+/// Matches a `/`-separated pattern against files under `base` with one of
+/// the given extensions, one path segment at a time. Hidden files only
+/// match patterns that start with `.`.
+pub fn glob(base: &Path, pattern: &str, extensions: &[&str]) -> Vec<String> {
+    let segments: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let mut prefixes = vec![String::new()];
+
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i == segments.len() - 1;
+        let mut next = Vec::new();
+        for prefix in &prefixes {
+            let join = |name: &str| {
+                if prefix.is_empty() { name.to_string() } else { format!("{}/{}", prefix, name) }
+            };
+            if !has_wildcard(segment) {
+                next.push(join(segment));
+                continue;
+            }
+            let Ok(entries) = std::fs::read_dir(base.join(prefix)) else {
+                continue;
+            };
+            let mut names: Vec<String> = entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|n| !n.starts_with('.') || segment.starts_with('.'))
+                .filter(|n| wildcard_match(segment, n))
+                .collect();
+            names.sort();
+            next.extend(names.iter().map(|n| join(n)));
+        }
+        if !last {
+            next.retain(|p| base.join(p).is_dir());
+        }
+        prefixes = next;
+    }
+
+    prefixes.retain(|p| {
+        let path = base.join(p);
+        path.is_file()
+            && path
+                .extension()
+                .map(|e| extensions.contains(&e.to_string_lossy().to_lowercase().as_str()))
+                .unwrap_or(false)
+    });
+    prefixes
+}
+
+/// Matches a relative path against a pattern segment by segment.
+fn matches_path(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    let pat: Vec<&str> = pattern.split('/').collect();
+    let segs: Vec<&str> = path.split('/').collect();
+    // A directory pattern matches everything beneath it.
+    if pat.len() < segs.len() && !has_wildcard(pattern) {
+        return segs.starts_with(&pat);
+    }
+    pat.len() == segs.len() && pat.iter().zip(&segs).all(|(p, s)| wildcard_match(p, s))
+}
+
+/// `*` matches any run of characters and `?` matches exactly one.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    let (mut pi, mut ni) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn pack(files: &[&str]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for f in files {
+            let path = dir.path().join(f);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        dir
+    }
+
+    fn rel(dir: &TempDir, clips: Vec<Clip>) -> Vec<String> {
+        clips
+            .iter()
+            .map(|c| c.path.strip_prefix(dir.path()).unwrap().display().to_string())
+            .collect()
+    }
+
+    fn expand(base: &Path, entries: &[String]) -> Vec<Clip> {
+        super::expand(base, entries, &HashMap::new())
+    }
+
+    fn entries(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn wildcard_match_handles_star_and_question_mark() {
+        assert!(wildcard_match("*.mp3", "hello.mp3"));
+        assert!(wildcard_match("a?c", "abc"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("annoying*.mp3", "annoying2.mp3"));
+        assert!(!wildcard_match("*.mp3", "hello.wav"));
+        assert!(!wildcard_match("a?c", "ac"));
+    }
+
+    #[test]
+    fn glob_expands_sorted_audio_files() {
+        let dir = pack(&["peon/b.mp3", "peon/a.mp3", "peon/c.wav", "peon/openpeon.json"]);
+        let out = expand(dir.path(), &entries(&["peon/*.mp3"]));
+        assert_eq!(rel(&dir, out), vec!["peon/a.mp3", "peon/b.mp3"]);
+    }
+
+    #[test]
+    fn directory_entry_includes_all_audio_files() {
+        let dir = pack(&["peon/b.mp3", "peon/a.wav", "peon/notes.txt", "peon/.hidden.mp3"]);
+        let out = expand(dir.path(), &entries(&["peon/"]));
+        assert_eq!(rel(&dir, out), vec!["peon/a.wav", "peon/b.mp3"]);
+    }
+
+    #[test]
+    fn glob_matches_across_directories() {
+        let dir = pack(&["a/x.mp3", "b/x.mp3", "b/y.mp3"]);
+        let out = expand(dir.path(), &entries(&["*/x.mp3"]));
+        assert_eq!(rel(&dir, out), vec!["a/x.mp3", "b/x.mp3"]);
+    }
+
+    #[test]
+    fn exclusions_remove_matches() {
+        let dir = pack(&["peon/annoying1.mp3", "peon/annoying2.mp3", "peon/ok.mp3"]);
+        let out = expand(dir.path(), &entries(&["peon/", "!peon/annoying*.mp3"]));
+        assert_eq!(rel(&dir, out), vec!["peon/ok.mp3"]);
+    }
+
+    #[test]
+    fn plain_entries_keep_order_and_dedupe() {
+        let dir = pack(&["peon/a.mp3", "peon/b.mp3"]);
+        let out = expand(dir.path(), &entries(&["peon/b.mp3", "peon/*.mp3", "missing.mp3"]));
+        assert_eq!(rel(&dir, out), vec!["peon/b.mp3", "peon/a.mp3", "missing.mp3"]);
+    }
+
+    #[test]
+    fn aliases_carry_library_options() {
+        let dir = pack(&["pack/ping.wav", "pack/pong.wav"]);
+        let mut library = HashMap::new();
+        library.insert(
+            "ding".to_string(),
+            SoundDef { file: "pack/ping.wav".into(), gain: 0.5, start: Some(0.1), end: None },
+        );
+        let clips = super::expand(dir.path(), &entries(&["@ding", "pack/*.wav", "@missing"]), &library);
+        assert_eq!(rel(&dir, clips.clone()), vec!["pack/ping.wav", "pack/pong.wav"]);
+        assert_eq!(clips[0].gain, 0.5);
+        assert_eq!(clips[0].start, Some(0.1));
+        assert_eq!(clips[1].gain, 1.0);
+    }
+
+    #[test]
+    fn absolute_entries_are_not_joined_to_base() {
+        let base = pack(&[]);
+        let other = pack(&["x/a.wav", "x/b.wav"]);
+        let abs = other.path().join("x/a.wav").display().to_string();
+        let glob = other.path().join("x/*.wav").display().to_string();
+
+        let out = expand(base.path(), &entries(&[&abs]));
+        assert_eq!(out[0].path, other.path().join("x/a.wav"));
+
+        let out = expand(base.path(), &entries(&[&glob, &format!("!{}", abs)]));
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].path, other.path().join("x/b.wav"));
+    }
+
+    #[test]
+    fn url_entries_map_to_cache_files() {
+        let base = pack(&[]);
+        let out = expand(base.path(), &entries(&["https://example.com/a/ping.wav?x=1"]));
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].url.as_deref(), Some("https://example.com/a/ping.wav?x=1"));
+        assert_eq!(out[0].path.extension().unwrap(), "wav");
+        assert!(out[0].path.parent().unwrap().ends_with("hookplayer/sounds"));
+    }
+
+    #[test]
+    fn validate_entry_accepts_supported_forms() {
+        for ok in ["pack/a.mp3", "/abs/a.mp3", "~/a.mp3", "https://x.io/a.mp3", "!http://x.io/a.mp3", "pack/*.mp3"] {
+            assert!(validate_entry(ok).is_ok(), "{}", ok);
+        }
+    }
+
+    #[test]
+    fn validate_entry_rejects_bad_urls_and_tildes() {
+        for bad in ["ftp://x.io/a.mp3", "https:///a.mp3", "https://x.io/*.mp3", "~bob/a.mp3"] {
+            assert!(validate_entry(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn aliases_can_be_excluded() {
+        let dir = pack(&["pack/ping.wav", "pack/pong.wav"]);
+        let mut library = HashMap::new();
+        library.insert(
+            "ding".to_string(),
+            SoundDef { file: "pack/ping.wav".into(), gain: 1.0, start: None, end: None },
+        );
+        let clips = super::expand(dir.path(), &entries(&["pack/", "!@ding"]), &library);
+        assert_eq!(rel(&dir, clips), vec!["pack/pong.wav"]);
+    }
+
+    #[test]
+    fn glob_without_matches_is_empty() {
+        let dir = pack(&[]);
+        assert!(expand(dir.path(), &entries(&["nope/*.mp3", "nope/"])).is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+use crate::config;
+use crate::fsutil;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Runtime state set by `mute`, `snooze` and `unmute`, kept apart from the
+/// config so toggling it never rewrites config.toml.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct State {
+    pub muted: bool,
+    /// Unix time in seconds until which playback is snoozed.
+    pub snoozed_until: Option<u64>,
+    /// Events that still play while muted or snoozed.
+    pub allow: Vec<String>,
+}
+
+impl State {
+    /// Loads the state file. A missing or unreadable file means not muted.
+    pub fn load() -> State {
+        std::fs::read_to_string(state_path())
+            .ok()
+            .and_then(|raw| serde_json::from_str(&raw).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let json = serde_json::to_string_pretty(self)? + "\n";
+        fsutil::write_atomic(&state_path(), json.as_bytes())?;
+        Ok(())
+    }
+
+    /// Returns true if `event` should stay silent at unix time `now`.
+    pub fn silences(&self, event: &str, now: u64) -> bool {
+        let quiet = self.muted || self.snoozed_until.is_some_and(|until| now < until);
+        quiet && !self.allows(event)
+    }
+
+    /// Allowed events also let their dotted children through, so allowing
+    /// `task.error` keeps `task.error.build` audible.
+    fn allows(&self, event: &str) -> bool {
+        self.allow.iter().any(|a| {
+            event == a || event.strip_prefix(a.as_str()).is_some_and(|rest| rest.starts_with('.'))
+        })
+    }
+}
+
+/// `$XDG_STATE_HOME/hookplayer/state.json`, or `~/.local/state/hookplayer/state.json`.
+fn state_path() -> PathBuf {
+    let dir = match std::env::var("XDG_STATE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("hookplayer"),
+        _ => config::expand_tilde("~/.local/state/hookplayer"),
+    };
+    dir.join("state.json")
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parses durations like `30m`, `2h`, `1h30m` or `45s` into seconds.
+/// A bare number is taken as minutes.
+pub fn parse_duration(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid duration '{}' (e.g. 30m, 2h, 1h30m)", s);
+    if let Ok(minutes) = s.parse::<u64>() {
+        return minutes.checked_mul(60).ok_or_else(invalid);
+    }
+
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let n: u64 = digits.parse().map_err(|_| invalid())?;
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            _ => return Err(invalid()),
+        };
+        total = n.checked_mul(unit).and_then(|secs| total.checked_add(secs)).ok_or_else(invalid)?;
+        digits.clear();
+    }
+    if !digits.is_empty() || total == 0 {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+/// Prints whether playback is muted or snoozed, and until when.
+pub fn print_status(state: &State) {
+    let now = now();
+    match state.snoozed_until {
+        _ if state.muted => println!("Muted until 'hookplayer unmute'."),
+        Some(until) if until > now => {
+            let local = chrono::DateTime::from_timestamp(until as i64, 0)
+                .map(|t| t.with_timezone(&chrono::Local).format("%H:%M").to_string())
+                .unwrap_or_default();
+            let mins = (until - now).div_ceil(60);
+            println!("Snoozed until {} ({} min left).", local, mins);
+        }
+        _ => {
+            println!("Not muted.");
+            return;
+        }
+    }
+    if !state.allow.is_empty() {
+        println!("Still playing: {}", state.allow.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("30m"), Ok(1800));
+        assert_eq!(parse_duration("2h"), Ok(7200));
+        assert_eq!(parse_duration("1h30m"), Ok(5400));
+        assert_eq!(parse_duration("45s"), Ok(45));
+        assert_eq!(parse_duration("15"), Ok(900));
+    }
+
+    #[test]
+    fn parse_duration_rejects_garbage() {
+        for bad in ["", "m", "10x", "1h30", "0m"] {
+            assert!(parse_duration(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_duration_rejects_overflow() {
+        for bad in ["18446744073709551615", "9999999999999999999d", "18446744073709551615s1s"] {
+            assert!(parse_duration(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn default_state_is_audible() {
+        assert!(!State::default().silences("stop", 100));
+    }
+
+    #[test]
+    fn mute_silences_everything_but_allowed() {
+        let state = State {
+            muted: true,
+            allow: vec!["permission".into(), "task.error".into()],
+            ..Default::default()
+        };
+        assert!(state.silences("stop", 100));
+        assert!(!state.silences("permission", 100));
+        assert!(!state.silences("task.error.build", 100));
+        assert!(state.silences("task.errors", 100));
+    }
+
+    #[test]
+    fn snooze_expires() {
+        let state = State { snoozed_until: Some(200), ..Default::default() };
+        assert!(state.silences("stop", 199));
+        assert!(!state.silences("stop", 200));
+    }
+}